@@ -0,0 +1,165 @@
+//! Member fingerprints, so that `pupi build` can skip members whose inputs have not changed.
+use crate::{Root, error::Result, graph};
+use std::{collections::BTreeMap, path::Path, sync::Mutex};
+
+/// Directory names under a member that hold build output or dependencies rather than inputs.
+const IGNORED: [&str; 6] = [
+    ".git",
+    ".parcel-cache",
+    ".pupi",
+    "dist",
+    "node_modules",
+    "target",
+];
+
+/// Fingerprints of the last successful build of each member, stored in `.pupi/` under the root.
+pub struct Cache {
+    path: String,
+    /// Rebuild everything, but still record fingerprints for the next run.
+    force: bool,
+    saved: Mutex<BTreeMap<String, String>>,
+    /// Fingerprints of the members' current inputs, computed at most once per run unless a
+    /// build changes them.
+    current: Mutex<BTreeMap<String, String>>,
+}
+
+/// 64-bit FNV-1a. Saved fingerprints must not change with the toolchain, which rules out
+/// `DefaultHasher` and the `Hash` impls of std types.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    /// Feed `bytes`, prefixed with their length so that consecutive fields cannot run together.
+    fn write(&mut self, bytes: &[u8]) {
+        for b in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+fn hash_dir(h: &mut Fnv, base: &Path, dir: &Path) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for e in entries {
+        let path = e.path();
+        let ty = e.file_type()?;
+        if ty.is_dir() {
+            if !IGNORED.iter().any(|i| e.file_name() == *i) {
+                hash_dir(h, base, &path)?;
+            }
+        } else if ty.is_file() {
+            let rel = path.strip_prefix(base).unwrap_or(&path);
+            h.write(rel.to_string_lossy().replace('\\', "/").as_bytes());
+            h.write(&std::fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+impl Cache {
+    pub fn load(root_path: &str, force: bool) -> Self {
+        let path = format!("{root_path}/.pupi/fingerprints.json");
+        // A missing or unreadable cache only means everything is rebuilt.
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Cache {
+            path,
+            force,
+            saved: Mutex::new(saved),
+            current: Mutex::default(),
+        }
+    }
+
+    fn compute(&self, root: &Root, root_path: &str, xpath: &str) -> Result<String> {
+        let member = &root.members[xpath];
+        let mut h = Fnv::new();
+        h.write(&serde_json::to_vec(member).map_err(std::io::Error::other)?);
+        let dir = format!("{root_path}/{xpath}");
+        if Path::new(&dir).is_dir() {
+            hash_dir(&mut h, Path::new(&dir), Path::new(&dir))?;
+        }
+        for d in graph::local_deps(member)
+            .into_iter()
+            .filter(|d| root.members.contains_key(*d))
+        {
+            h.write(d.as_bytes());
+            h.write(self.fingerprint(root, root_path, d)?.as_bytes());
+        }
+        Ok(h.hex())
+    }
+
+    /// The fingerprint of `xpath`'s source files, its `Member` config and the fingerprints of its
+    /// `deps`.
+    pub fn fingerprint(&self, root: &Root, root_path: &str, xpath: &str) -> Result<String> {
+        if let Some(f) = self.current.lock().unwrap().get(xpath) {
+            return Ok(f.clone());
+        }
+        let f = self.compute(root, root_path, xpath)?;
+        self.current
+            .lock()
+            .unwrap()
+            .insert(xpath.to_owned(), f.clone());
+        Ok(f)
+    }
+
+    /// Whether `xpath`'s inputs are unchanged since its last successful build.
+    pub fn fresh(&self, root: &Root, root_path: &str, xpath: &str) -> Result<bool> {
+        if self.force {
+            return Ok(false);
+        }
+        let f = self.fingerprint(root, root_path, xpath)?;
+        Ok(self.saved.lock().unwrap().get(xpath) == Some(&f))
+    }
+
+    /// Remember `xpath` as built, fingerprinting its inputs as the build left them.
+    pub fn record(&self, root: &Root, root_path: &str, xpath: &str) -> Result<()> {
+        let f = self.compute(root, root_path, xpath)?;
+        self.current
+            .lock()
+            .unwrap()
+            .insert(xpath.to_owned(), f.clone());
+        self.saved.lock().unwrap().insert(xpath.to_owned(), f);
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let saved = self.saved.lock().unwrap();
+        if let Some(dir) = Path::new(&self.path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_vec_pretty(&*saved).map_err(std::io::Error::other)?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_is_stable() {
+        // Changing these values invalidates every saved `.pupi/fingerprints.json`.
+        assert_eq!(Fnv::new().hex(), "cbf29ce484222325");
+        let mut h = Fnv::new();
+        h.write(b"");
+        assert_eq!(h.hex(), "a8c7f832281a39c5");
+        let mut h = Fnv::new();
+        h.write(b"a");
+        h.write(b"bc");
+        let mut j = Fnv::new();
+        j.write(b"ab");
+        j.write(b"c");
+        assert_ne!(h.hex(), j.hex());
+    }
+}
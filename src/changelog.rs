@@ -0,0 +1,155 @@
+use crate::{Options, Root, error::Result, git, version::Change, write};
+use schemars::JsonSchema;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::SystemTime};
+
+/// Keeps a CHANGELOG.md in every member directory, updated by `pupi version`.
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[non_exhaustive]
+pub struct Changelog {
+    /// Also keep a CHANGELOG.md at the workspace root listing the members of each release.
+    #[serde(default)]
+    pub root: bool,
+}
+
+const HEADER: &str = "# Changelog\n";
+
+/// Today's date as `YYYY-MM-DD` (UTC).
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400) as i64;
+    // Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+/// `old` with `section` inserted above its newest section, which is the first line starting with
+/// `## `. An empty changelog gets a header first.
+fn prepend(old: &str, section: &str) -> String {
+    let at = if old.starts_with("## ") {
+        0
+    } else {
+        old.find("\n## ").map_or(old.len(), |i| i + 1)
+    };
+    let (head, rest) = old.split_at(at);
+    let sep = if rest.is_empty() { "" } else { "\n" };
+    match head.trim_end() {
+        "" if !rest.is_empty() => format!("{section}{sep}{rest}"),
+        "" => format!("{HEADER}\n{section}"),
+        h => format!("{h}\n\n{section}{sep}{rest}"),
+    }
+}
+
+/// Insert `section` above the newest existing section of the changelog at `path`.
+fn insert(opts: &Options, path: &str, section: &str) -> Result<()> {
+    let old = std::fs::read_to_string(path).unwrap_or_default();
+    write(opts, path, prepend(&old, section))
+}
+
+/// The section for `xpath`'s release `version`: its commits since the last release tag grouped
+/// by type, and the members it depends on that changed in the same run.
+fn section(
+    root: &Root,
+    root_path: &str,
+    tags: &git::Tags,
+    xpath: &str,
+    version: &Version,
+    versions: &BTreeMap<String, Version>,
+    date: &str,
+) -> Result<String> {
+    let last = tags.last_release(xpath)?;
+    let commits = git::log(root_path, last.as_ref().map(|(_, t)| t.as_str()), xpath)?;
+    let mut groups: BTreeMap<_, Vec<String>> = BTreeMap::new();
+    for (subject, body) in commits.iter() {
+        let change = Change::of(subject, body);
+        let text = match change {
+            Some(_) => subject
+                .split_once(':')
+                .map_or(&**subject, |(_, d)| d.trim()),
+            None => subject,
+        };
+        groups
+            .entry(std::cmp::Reverse(change))
+            .or_default()
+            .push(format!("- {text}\n"));
+    }
+    let mut s = format!("## {version} - {date}\n");
+    for (std::cmp::Reverse(change), lines) in groups {
+        s.push_str(match change {
+            Some(Change::Breaking) => "\n### Breaking Changes\n\n",
+            Some(Change::Feat) => "\n### Features\n\n",
+            Some(Change::Fix) => "\n### Fixes\n\n",
+            None => "\n### Other\n\n",
+        });
+        s.extend(lines);
+    }
+    let deps = root.members[xpath]
+        .deps
+        .keys()
+        .filter_map(|d| Some(format!("- `{d}` {}\n", versions.get(d)?)))
+        .collect::<String>();
+    if !deps.is_empty() {
+        s.push_str("\n### Dependencies\n\n");
+        s.push_str(&deps);
+    }
+    Ok(s)
+}
+
+/// Add a section for every member in `versions` to its CHANGELOG.md, and a release entry to the
+/// root CHANGELOG.md if enabled. Must run before the new release tags exist.
+pub fn update(
+    opts: &Options,
+    root: &Root,
+    root_path: &str,
+    tags: &git::Tags,
+    versions: &BTreeMap<String, Version>,
+) -> Result<()> {
+    let Some(config) = root.core.as_ref().and_then(|c| c.changelog.as_ref()) else {
+        return Ok(());
+    };
+    let date = today();
+    for (xpath, version) in versions.iter() {
+        let s = section(root, root_path, tags, xpath, version, versions, &date)?;
+        insert(opts, &format!("{root_path}/{xpath}/CHANGELOG.md"), &s)?;
+    }
+    if config.root && !versions.is_empty() {
+        let mut s = format!("## {date}\n\n");
+        for (xpath, version) in versions.iter() {
+            let old = &root.members[xpath].version;
+            s.push_str(&format!("- `{xpath}` {old} -> {version}\n"));
+        }
+        insert(opts, &format!("{root_path}/CHANGELOG.md"), &s)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepend_sections() {
+        let new = "## 1.1.0\n\n- b\n";
+        for (old, expected) in [
+            ("", "# Changelog\n\n## 1.1.0\n\n- b\n"),
+            ("# Changelog\n", "# Changelog\n\n## 1.1.0\n\n- b\n"),
+            (
+                "# Changelog\n\n## 1.0.0\n\n- a\n",
+                "# Changelog\n\n## 1.1.0\n\n- b\n\n## 1.0.0\n\n- a\n",
+            ),
+            ("## 1.0.0\n\n- a\n", "## 1.1.0\n\n- b\n\n## 1.0.0\n\n- a\n"),
+        ] {
+            assert_eq!(prepend(old, new), expected, "{old:?}");
+        }
+    }
+}
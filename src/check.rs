@@ -0,0 +1,158 @@
+use crate::{
+    Dep, DepMap, Range, Root, config_path,
+    error::{ErrorKind, Result},
+    graph::Graph,
+};
+use std::{fmt, path::Path};
+
+/// A problem with the configuration, located by file and key.
+pub struct Diagnostic {
+    pub file: String,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.file, self.key, self.message)
+    }
+}
+
+struct Checker<'a> {
+    file: String,
+    root_path: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, key: String, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: self.file.clone(),
+            key,
+            message,
+        });
+    }
+
+    /// Check that `key` names a member of `root`. A `Dep::subrepo` dep is checked in two parts:
+    /// `pkg_name` (with `pkg`) in `root`, and `key` (with `nest`) in the subroot at
+    /// `<pkg_name>/<subrepo>`, which must be one of `pkg_name`'s subtree or submodule paths.
+    fn dep(
+        &mut self,
+        at: String,
+        root: &Root,
+        root_path: &str,
+        depmap: &DepMap,
+        key: &str,
+        dep: &Dep,
+    ) {
+        let Some(s) = dep.subrepo.as_ref() else {
+            if !root.members.contains_key(key) {
+                self.report(at, format!("`{key}` is not a member of {root_path}"));
+            }
+            return;
+        };
+        self.dep(
+            format!("{at}.subrepo.pkg"),
+            root,
+            root_path,
+            depmap,
+            &s.pkg_name,
+            &s.pkg,
+        );
+        let name = format!("{}/{}", &s.pkg_name, &s.subrepo);
+        match depmap.subroot(root, root_path, &name) {
+            Ok(Some((sub, sub_path, sub_depmap))) => {
+                self.dep(
+                    format!("{at}.subrepo.nest"),
+                    sub,
+                    &sub_path,
+                    sub_depmap,
+                    key,
+                    &s.nest,
+                );
+            }
+            Ok(None) => self.report(
+                format!("{at}.subrepo"),
+                format!(
+                    "`{name}` is not a subtree or submodule path of `{}`",
+                    s.pkg_name
+                ),
+            ),
+            Err(e) => self.report(format!("{at}.subrepo"), e.to_string()),
+        }
+    }
+
+    fn member(&mut self, root: &Root, depmap: &DepMap, xpath: &str) {
+        let member = &root.members[xpath];
+        let dir = format!("{}/{xpath}", self.root_path);
+        for (key, dep) in member.deps.iter() {
+            self.dep(
+                format!("[{xpath:?}].deps[{key:?}]"),
+                root,
+                self.root_path,
+                depmap,
+                key,
+                dep,
+            );
+        }
+        // Private members are never published, so nothing resolves their ranges but the workspace.
+        if let Some(npm) = member.npm.as_ref().filter(|_| !member.private) {
+            for (section, range) in npm.ranges.sections() {
+                if matches!(range, Range::Workspace) && section != "devDependencies" {
+                    self.report(
+                        format!("[{xpath:?}].npm.ranges.{section}"),
+                        "`workspace:*` is only allowed for devDependencies of public members, as \
+                         `npm publish` does not resolve it"
+                            .to_owned(),
+                    );
+                }
+            }
+        }
+        if !Path::new(&dir).is_dir() {
+            self.report(
+                format!("[{xpath:?}]"),
+                format!("directory {dir} does not exist"),
+            );
+            return;
+        }
+        for (enabled, key, manifest) in [
+            (member.cargo.is_some(), "cargo", "Cargo.toml"),
+            (member.npm.is_some(), "npm", "package.json"),
+        ] {
+            if enabled && !Path::new(&format!("{dir}/{manifest}")).exists() {
+                self.report(
+                    format!("[{xpath:?}].{key}"),
+                    format!("{dir}/{manifest} does not exist"),
+                );
+            }
+        }
+    }
+}
+
+/// Find every configuration problem in `root` without touching the disk.
+pub fn check(root: &Root, root_path: &str, depmap: &DepMap) -> Vec<Diagnostic> {
+    let mut c = Checker {
+        file: config_path(root_path, "pupi").unwrap_or_else(|| format!("{root_path}/pupi.json")),
+        root_path,
+        diagnostics: vec![],
+    };
+    for xpath in root.members.keys() {
+        c.member(root, depmap, xpath);
+    }
+    c.diagnostics
+}
+
+/// Print every configuration problem and fail if there are any, then fail on dependency cycles.
+pub fn validate(root: &Root, root_path: &str, depmap: &DepMap) -> Result<()> {
+    let diagnostics = check(root, root_path, depmap);
+    for d in diagnostics.iter() {
+        eprintln!("[Check] {d}");
+    }
+    if !diagnostics.is_empty() {
+        return Err(ErrorKind::Invalid(diagnostics.len()).into());
+    }
+    if let Some(cycle) = Graph::build(root, root_path, depmap)?.find_cycle() {
+        return Err(ErrorKind::Cycle(cycle).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use crate::{graph::Format, output::Mode, version::Bump};
+use clap::{Args, Parser, Subcommand};
+
+/// Blazingly fast meta-build tool for our open-source libraries
+#[derive(Parser)]
+#[command(name = "pupi", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Initialize a new workspace (git, pupi.json, package.json, Cargo.toml)
+    Setup {
+        /// Workspace root
+        #[arg(default_value = ".")]
+        root_path: String,
+    },
+    /// Print the JSON schema for pupi.json / pupi.yaml
+    Schema,
+    /// Validate pupi.json: dependency keys, subrepo paths, member directories and manifests
+    Check {
+        /// Workspace root
+        #[arg(default_value = ".")]
+        root_path: String,
+    },
+    /// Print the member dependency graph as Graphviz DOT, Mermaid or JSON
+    Graph {
+        /// Workspace root
+        #[arg(default_value = ".")]
+        root_path: String,
+        #[arg(long, value_enum, default_value_t = Format::Dot)]
+        format: Format,
+    },
+    /// List members matching filters, one per line
+    #[command(visible_alias = "ls")]
+    Query(QueryArgs),
+    /// Sync manifests and build every member
+    Build(RunArgs),
+    /// Sync manifests, build every member and run its tests (`cargo test`, the package.json
+    /// `test` script); arguments after `--` are passed on to both
+    Test(RunArgs),
+    /// Sync manifests, build and publish every non-private member
+    Publish {
+        #[command(flatten)]
+        run: RunArgs,
+        #[command(flatten)]
+        publish: PublishArgs,
+    },
+    /// Sync manifests and run `updater` scripts
+    Autogen(RunArgs),
+    /// Sync manifests only
+    Update(RunArgs),
+    /// Bump a member's version in pupi.json, cascading to its dependents
+    Version(VersionArgs),
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Workspace root
+    #[arg(default_value = ".")]
+    pub root_path: String,
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// Print planned manifest diffs and commands without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Rebuild members even if their inputs are unchanged since the last successful build
+    #[arg(long)]
+    pub force: bool,
+    /// Keep building members that do not depend on a failed one instead of stopping early
+    #[arg(long, short = 'k')]
+    pub keep_going: bool,
+    /// Members processed in parallel [default: number of CPUs]
+    #[arg(long, short = 'j', value_name = "N")]
+    pub jobs: Option<usize>,
+    /// External tools (cargo, npm, npx, git, updater scripts) run in parallel [default: --jobs]
+    #[arg(long, value_name = "N")]
+    pub tool_jobs: Option<usize>,
+    /// Print child output live (`stream`) or per member once it finishes (`grouped`)
+    #[arg(long, value_enum, default_value_t = Mode::Stream)]
+    pub output: Mode,
+    /// Extra arguments forwarded to `updater` scripts, and to the test runners for `pupi test`
+    #[arg(last = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Args, Default)]
+pub struct PublishArgs {
+    /// Refuse to start on a dirty working tree; afterwards commit the manifest rewrites and
+    /// create an annotated tag per published member
+    #[arg(long)]
+    pub release: bool,
+    /// Seconds to wait for each published version to show up in its registry before publishing
+    /// members that depend on it (0 to not wait)
+    #[arg(long, value_name = "SECS", default_value_t = 300)]
+    pub wait: u64,
+    /// Seconds between registry checks while waiting
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    pub poll: u64,
+}
+
+#[derive(Args)]
+pub struct VersionArgs {
+    /// Member to bump
+    #[arg(required_unless_present = "auto")]
+    pub member: Option<String>,
+    /// `major`, `minor`, `patch`, `prerelease` or an exact version
+    #[arg(required_unless_present = "auto")]
+    pub bump: Option<Bump>,
+    /// Bump every member from the conventional commits under it since its last release tag
+    #[arg(long, conflicts_with_all = ["member", "bump"])]
+    pub auto: bool,
+    /// Workspace root
+    #[arg(long = "root", value_name = "PATH", default_value = ".")]
+    pub root_path: String,
+    /// Print the planned pupi.json diff without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Default)]
+pub struct QueryArgs {
+    /// Workspace root
+    #[arg(default_value = ".")]
+    pub root_path: String,
+    /// Only members with `cargo` enabled
+    #[arg(long)]
+    pub cargo: bool,
+    /// Only members with `npm` enabled
+    #[arg(long)]
+    pub npm: bool,
+    /// Only members that are not `private`
+    #[arg(long)]
+    pub public: bool,
+    /// Only members that list a member matching this path or glob in their `deps`
+    #[arg(long, value_name = "PATTERN")]
+    pub depends_on: Option<String>,
+    /// Only members that transitively depend on a member matching this path or glob
+    #[arg(long, value_name = "PATTERN")]
+    pub dependents_of: Option<String>,
+    /// Only members with files changed since this git ref
+    #[arg(long, value_name = "REF")]
+    pub changed_since: Option<String>,
+    /// Line printed per member, with `{path}`, `{npm}` (package name), `{crate}` (crate name)
+    /// and `{version}` placeholders
+    #[arg(long, default_value = "{path}")]
+    pub format: String,
+}
+
+impl Command {
+    /// The subcommand name, as passed through to build systems and `updater` scripts.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Setup { .. } => "setup",
+            Command::Schema => "schema",
+            Command::Check { .. } => "check",
+            Command::Graph { .. } => "graph",
+            Command::Query(_) => "query",
+            Command::Build(_) => "build",
+            Command::Test(_) => "test",
+            Command::Publish { .. } => "publish",
+            Command::Autogen(_) => "autogen",
+            Command::Update(_) => "update",
+            Command::Version(_) => "version",
+        }
+    }
+}
+
+#[derive(Args, Default)]
+pub struct SelectArgs {
+    /// Only process members matching this path or glob (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+    /// Skip members matching this path or glob (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// Process matching members plus everything they transitively depend on (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub with_deps: Vec<String>,
+    /// Process matching members plus everything that transitively depends on them (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub with_dependents: Vec<String>,
+    /// Process members with files changed since this git ref, plus everything that
+    /// transitively depends on them
+    #[arg(long, value_name = "REF")]
+    pub since: Option<String>,
+}
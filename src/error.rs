@@ -0,0 +1,182 @@
+use std::{fmt, process::ExitStatus};
+
+/// The step of a run an [`Error`] happened in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Config,
+    Workspace,
+    Subtree,
+    Submodule,
+    Updater,
+    Cargo,
+    Npm,
+    Release,
+    Cache,
+    Test,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Config => "config",
+            Phase::Workspace => "workspace",
+            Phase::Subtree => "subtree",
+            Phase::Submodule => "submodule",
+            Phase::Updater => "updater",
+            Phase::Cargo => "cargo",
+            Phase::Npm => "npm",
+            Phase::Release => "release",
+            Phase::Cache => "cache",
+            Phase::Test => "test",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// `pupi.json`/`pupi.yaml` could not be found or parsed.
+    #[error("{path}: {message}")]
+    Config { path: String, message: String },
+    /// A `Cargo.toml`/`package.json` could not be parsed or lacks a required field.
+    #[error("{path}: {message}")]
+    Manifest { path: String, message: String },
+    #[error("missing manifest {path}")]
+    MissingManifest { path: String },
+    #[error("`{command}` exited with {status}")]
+    Command {
+        command: String,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+    #[error("`{command}` exited with {status}")]
+    Git {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// `pupi check` found problems; each one has already been printed.
+    #[error("configuration has {0} problem(s)")]
+    Invalid(usize),
+    #[error("dependency cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    /// Several independent steps failed, e.g. more than one subtree path.
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    Many(Vec<Error>),
+    /// The run finished with failed members; details are in the summary.
+    #[error("{0} member(s) failed")]
+    Failed(usize),
+    /// `--release` needs a clean working tree to commit and tag.
+    #[error("working tree at {path} has uncommitted changes")]
+    Dirty { path: String },
+    /// A published version did not become visible in the registry within `--wait` seconds.
+    #[error("`{name}@{version}` did not show up in the registry within {secs}s")]
+    Unresolved {
+        name: String,
+        version: String,
+        secs: u64,
+    },
+    /// `--only`/`--exclude`/... did not resolve.
+    #[error("{0}")]
+    Selection(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// An [`ErrorKind`] together with the member and phase it happened in, when known.
+#[derive(Debug)]
+pub struct Error {
+    pub member: Option<String>,
+    pub phase: Option<Phase>,
+    pub kind: ErrorKind,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Attach member/phase context, keeping any context that is already more specific.
+    pub fn at(mut self, member: &str, phase: Phase) -> Self {
+        self.member.get_or_insert_with(|| member.to_owned());
+        self.phase.get_or_insert(phase);
+        self
+    }
+
+    /// Attach phase context to an error that is not tied to a single member.
+    pub fn in_phase(mut self, phase: Phase) -> Self {
+        self.phase.get_or_insert(phase);
+        self
+    }
+
+    /// Combine the errors of independent steps, succeeding if there are none.
+    pub fn collect(mut errors: Vec<Error>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ErrorKind::Many(errors).into()),
+        }
+    }
+
+    /// Split an [`ErrorKind::Many`] back into its parts, passing down its context.
+    pub fn flatten(self) -> Vec<Error> {
+        match self.kind {
+            ErrorKind::Many(v) => v
+                .into_iter()
+                .flat_map(|mut e| {
+                    if e.member.is_none() {
+                        e.member.clone_from(&self.member);
+                    }
+                    e.phase = e.phase.or(self.phase);
+                    e.flatten()
+                })
+                .collect(),
+            _ => vec![self],
+        }
+    }
+
+    /// The message printed by `main`, including the tail of captured output for failed commands.
+    pub fn render(&self) -> String {
+        let mut s = self.to_string();
+        if let ErrorKind::Command { stderr, .. } | ErrorKind::Git { stderr, .. } = &self.kind {
+            let lines = stderr.lines().collect::<Vec<_>>();
+            for l in &lines[lines.len().saturating_sub(20)..] {
+                s.push_str("\n    ");
+                s.push_str(l);
+            }
+        }
+        s
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.member, &self.phase) {
+            (Some(m), Some(p)) => write!(f, "[{m} {p}] {}", self.kind),
+            (Some(m), None) => write!(f, "[{m}] {}", self.kind),
+            (None, Some(p)) => write!(f, "[{p}] {}", self.kind),
+            (None, None) => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.kind)
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            member: None,
+            phase: None,
+            kind,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        ErrorKind::Io(e).into()
+    }
+}
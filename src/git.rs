@@ -0,0 +1,119 @@
+//! Read-only git queries. Commands that change the repository go through `out` instead.
+use crate::{
+    DepMap, Root,
+    error::{ErrorKind, Result},
+};
+use std::process::Command;
+
+/// Run `git args...` in `dir` and return its stdout.
+pub fn query(dir: &str, args: &[&str]) -> Result<String> {
+    let o = Command::new("git").args(args).current_dir(dir).output()?;
+    if !o.status.success() {
+        return Err(ErrorKind::Git {
+            command: format!("git {}", args.join(" ")),
+            status: o.status,
+            stderr: String::from_utf8_lossy(&o.stderr).into_owned(),
+        }
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&o.stdout).into_owned())
+}
+
+/// The tag format used when `RootCore::tag_format` is not set.
+pub const DEFAULT_TAG_FORMAT: &str = "{xpath}-v{version}";
+
+/// Release tag naming for the members of one root, following `RootCore::tag_format`.
+pub struct Tags<'a> {
+    root: &'a Root,
+    root_path: &'a str,
+    depmap: &'a DepMap,
+    format: &'a str,
+}
+
+impl<'a> Tags<'a> {
+    pub fn new(root: &'a Root, root_path: &'a str, depmap: &'a DepMap) -> Self {
+        let format = root.core.as_ref().and_then(|c| c.tag_format.as_deref());
+        Tags {
+            root,
+            root_path,
+            depmap,
+            format: format.unwrap_or(DEFAULT_TAG_FORMAT),
+        }
+    }
+
+    /// The published name of `xpath`: its crate name, else its npm name, else its xpath.
+    fn name(&self, xpath: &str) -> Result<String> {
+        let member = &self.root.members[xpath];
+        let name = if member.cargo.is_some() {
+            self.depmap.cargo(self.root, self.root_path)?.get(xpath)
+        } else if member.npm.is_some() {
+            self.depmap.npm(self.root, self.root_path)?.get(xpath)
+        } else {
+            None
+        };
+        Ok(name.map_or(xpath, |n| n).to_owned())
+    }
+
+    /// The release tag of member `xpath` at `version`. `{xpath}` is substituted without a
+    /// leading `./` or trailing `/`, so `./pkg/a/` and `pkg/a` are tagged alike.
+    pub fn tag(&self, xpath: &str, version: &str) -> Result<String> {
+        let mut path = xpath.trim_end_matches('/');
+        while let Some(p) = path.strip_prefix("./") {
+            path = p;
+        }
+        let mut tag = self.format.replace("{xpath}", path);
+        if tag.contains("{name}") {
+            tag = tag.replace("{name}", &self.name(xpath)?);
+        }
+        Ok(tag.replace("{version}", version))
+    }
+
+    /// The highest version of `xpath` that has a release tag, with that tag.
+    pub fn last_release(&self, xpath: &str) -> Result<Option<(semver::Version, String)>> {
+        let pattern = self.tag(xpath, "*")?;
+        let (prefix, suffix) = pattern.split_once('*').unwrap_or((&pattern, ""));
+        let tags = query(self.root_path, &["tag", "--list", &pattern])?;
+        Ok(tags
+            .lines()
+            .filter_map(|t| {
+                let v = t.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some((v.parse().ok()?, t.to_owned()))
+            })
+            .max())
+    }
+}
+
+/// A commit, as `(subject, body)`.
+pub type Commit = (String, String);
+
+/// Commits reachable from `HEAD` but not from `since` (all of them if `None`) that touch `path`,
+/// newest first.
+pub fn log(root_path: &str, since: Option<&str>, path: &str) -> Result<Vec<Commit>> {
+    let range = since.map_or_else(|| "HEAD".to_owned(), |s| format!("{s}..HEAD"));
+    let log = query(
+        root_path,
+        &["log", "--format=%s%x00%b%x1e", &range, "--", path],
+    )?;
+    Ok(log
+        .split('\x1e')
+        .filter_map(|c| {
+            let (subject, body) = c.trim_start_matches('\n').split_once('\0')?;
+            Some((subject.to_owned(), body.trim().to_owned()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::members;
+
+    #[test]
+    fn tags_normalize_xpath() {
+        let root = members(&[("./pkg/a/", &[]), ("pkg/b", &[])]);
+        let d = DepMap::default();
+        let tags = Tags::new(&root, ".", &d);
+        assert_eq!(tags.tag("./pkg/a/", "1.0.0").unwrap(), "pkg/a-v1.0.0");
+        assert_eq!(tags.tag("pkg/b", "*").unwrap(), "pkg/b-v*");
+    }
+}
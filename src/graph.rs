@@ -0,0 +1,507 @@
+use crate::{Dep, DepMap, Member, Root, error::Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How `pupi graph` prints the graph.
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// A Mermaid flowchart
+    Mermaid,
+    /// `{"nodes": {...}, "edges": [...]}`
+    Json,
+}
+
+/// What a member of the graph is, as shown by `pupi graph`.
+#[derive(Serialize)]
+pub struct Node {
+    pub cargo: bool,
+    pub npm: bool,
+    pub private: bool,
+    /// The subroot the member belongs to, relative to the top-level root; empty for its own
+    /// members.
+    pub root: String,
+}
+
+impl Node {
+    fn new(member: &Member, root: &str) -> Node {
+        Node {
+            cargo: member.cargo.is_some(),
+            npm: member.npm.is_some(),
+            private: member.private,
+            root: root.to_owned(),
+        }
+    }
+
+    /// The ecosystems and `private`, e.g. `cargo, npm, private`.
+    fn tags(&self) -> String {
+        [
+            (self.cargo, "cargo"),
+            (self.npm, "npm"),
+            (self.private, "private"),
+        ]
+        .into_iter()
+        .filter_map(|(on, t)| on.then_some(t))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// The member dependency graph, including members of subroots reached through `Dep::subrepo`.
+///
+/// Nodes are member paths relative to the top-level root, so a member `x` of the subroot
+/// `pkg/vendor` is `pkg/vendor/x`.
+#[derive(Default)]
+pub struct Graph {
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+    pub nodes: BTreeMap<String, Node>,
+}
+
+fn join(prefix: &str, xpath: &str) -> String {
+    match prefix {
+        "" => xpath.to_owned(),
+        _ => format!("{prefix}/{xpath}"),
+    }
+}
+
+/// A root together with where it lives, both on disk and relative to the top-level root.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    root: &'a Root,
+    root_path: &'a str,
+    prefix: &'a str,
+    depmap: &'a DepMap,
+}
+
+impl Graph {
+    /// Walk every member of `root` and every subroot member its deps reach.
+    pub fn build(root: &Root, root_path: &str, depmap: &DepMap) -> Result<Graph> {
+        let mut g = Graph::default();
+        g.walk(Scope {
+            root,
+            root_path,
+            prefix: "",
+            depmap,
+        })?;
+        Ok(g)
+    }
+
+    fn walk(&mut self, scope: Scope) -> Result<()> {
+        for (xpath, member) in scope.root.members.iter() {
+            let node = join(scope.prefix, xpath);
+            if self.edges.contains_key(&node) {
+                continue;
+            }
+            self.edges.insert(node.clone(), BTreeSet::new());
+            self.nodes
+                .insert(node.clone(), Node::new(member, scope.prefix));
+            let mut targets = BTreeSet::new();
+            for (key, dep) in member.deps.iter() {
+                self.targets(scope, key, dep, &mut targets)?;
+            }
+            self.edges.insert(node, targets);
+        }
+        Ok(())
+    }
+
+    /// The nodes a `deps` entry points at. A plain dep points at `key` in the current root. A
+    /// `Dep::subrepo` dep points at `pkg_name` (resolved through `pkg`) and at `key` (resolved
+    /// through `nest`) in the subroot at `<pkg_name>/<subrepo>`, or at `key` in the current root
+    /// if that subroot does not exist.
+    fn targets(
+        &mut self,
+        scope: Scope,
+        key: &str,
+        dep: &Dep,
+        out: &mut BTreeSet<String>,
+    ) -> Result<()> {
+        let Some(s) = dep.subrepo.as_ref() else {
+            out.insert(join(scope.prefix, key));
+            return Ok(());
+        };
+        self.targets(scope, &s.pkg_name, &s.pkg, out)?;
+        let name = format!("{}/{}", &s.pkg_name, &s.subrepo);
+        match scope.depmap.subroot(scope.root, scope.root_path, &name)? {
+            Some((root, root_path, depmap)) => {
+                let prefix = join(scope.prefix, &name);
+                let inner = Scope {
+                    root,
+                    root_path: &root_path,
+                    prefix: &prefix,
+                    depmap,
+                };
+                self.walk(inner)?;
+                self.targets(inner, key, &s.nest, out)
+            }
+            None => {
+                out.insert(join(scope.prefix, key));
+                Ok(())
+            }
+        }
+    }
+
+    /// Every edge, and whether it crosses into another subroot.
+    fn edge_list(&self) -> impl Iterator<Item = (&str, &str, bool)> {
+        self.edges.iter().flat_map(move |(from, to)| {
+            to.iter().map(move |to| {
+                let root = |n: &str| self.nodes.get(n).map(|n| &*n.root);
+                (&**from, &**to, root(from) != root(to))
+            })
+        })
+    }
+
+    /// The graph in `format`. Edges point from a member to its dependencies. Subroots are
+    /// grouped, private members and edges into another subroot are dashed.
+    pub fn render(&self, format: Format) -> String {
+        let mut groups: BTreeMap<&str, Vec<(&str, &Node)>> = BTreeMap::new();
+        for (name, node) in self.nodes.iter() {
+            groups.entry(&node.root).or_default().push((name, node));
+        }
+        let label = |name: &str, node: &Node| match node.tags() {
+            t if t.is_empty() => name.to_owned(),
+            t => format!("{name} ({t})"),
+        };
+        match format {
+            Format::Dot => {
+                let mut s = "digraph pupi {\n".to_owned();
+                for (root, nodes) in groups {
+                    let indent = if root.is_empty() { "  " } else { "    " };
+                    if !root.is_empty() {
+                        s.push_str(&format!("  subgraph {:?} {{\n", format!("cluster_{root}")));
+                        s.push_str(&format!("    label={root:?};\n"));
+                    }
+                    for (name, node) in nodes {
+                        let style = if node.private { ", style=dashed" } else { "" };
+                        let label = label(name, node);
+                        s.push_str(&format!("{indent}{name:?} [label={label:?}{style}];\n"));
+                    }
+                    if !root.is_empty() {
+                        s.push_str("  }\n");
+                    }
+                }
+                for (from, to, cross) in self.edge_list() {
+                    let style = if cross { " [style=dashed]" } else { "" };
+                    s.push_str(&format!("  {from:?} -> {to:?}{style};\n"));
+                }
+                s.push_str("}\n");
+                s
+            }
+            Format::Mermaid => {
+                // Mermaid ids cannot contain `/`, so nodes are numbered and labelled instead.
+                let mut ids = BTreeMap::new();
+                let mut id = |n: &str| {
+                    let next = ids.len();
+                    format!("n{}", ids.entry(n.to_owned()).or_insert(next))
+                };
+                let mut s = "flowchart LR\n".to_owned();
+                let mut private = vec![];
+                for (i, (root, nodes)) in groups.into_iter().enumerate() {
+                    let indent = if root.is_empty() { "  " } else { "    " };
+                    if !root.is_empty() {
+                        s.push_str(&format!("  subgraph s{i}[{root:?}]\n"));
+                    }
+                    for (name, node) in nodes {
+                        if node.private {
+                            private.push(id(name));
+                        }
+                        s.push_str(&format!("{indent}{}[{:?}]\n", id(name), label(name, node)));
+                    }
+                    if !root.is_empty() {
+                        s.push_str("  end\n");
+                    }
+                }
+                for (from, to, cross) in self.edge_list() {
+                    let arrow = if cross { "-.->" } else { "-->" };
+                    s.push_str(&format!("  {} {arrow} {}\n", id(from), id(to)));
+                }
+                if !private.is_empty() {
+                    s.push_str("  classDef private stroke-dasharray: 5 5\n");
+                    s.push_str(&format!("  class {} private\n", private.join(",")));
+                }
+                s
+            }
+            Format::Json => {
+                let edges = self
+                    .edge_list()
+                    .map(|(from, to, cross)| {
+                        serde_json::json!({ "from": from, "to": to, "subroot": cross })
+                    })
+                    .collect::<Vec<_>>();
+                let val = serde_json::json!({ "nodes": self.nodes, "edges": edges });
+                format!("{:#}\n", val)
+            }
+        }
+    }
+
+    /// The first dependency cycle found, as a path that starts and ends with the same node.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            g: &'a Graph,
+            node: &'a str,
+            stack: &mut Vec<&'a str>,
+            done: &mut BTreeSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(i) = stack.iter().position(|n| *n == node) {
+                let mut cycle = stack[i..].iter().map(|n| n.to_string()).collect::<Vec<_>>();
+                cycle.push(node.to_owned());
+                return Some(cycle);
+            }
+            if !done.insert(node) {
+                return None;
+            }
+            stack.push(node);
+            for next in g.edges.get(node).into_iter().flatten() {
+                if let Some(c) = visit(g, next, stack, done) {
+                    return Some(c);
+                }
+            }
+            stack.pop();
+            None
+        }
+        let mut done = BTreeSet::new();
+        self.edges
+            .keys()
+            .find_map(|n| visit(self, n, &mut vec![], &mut done))
+    }
+}
+
+/// The member of the same root that a `deps` entry makes its member wait for: `key` itself, or
+/// for a `Dep::subrepo` dep, `pkg_name` (resolved through `pkg`), whose subtree or submodule
+/// holds the subroot. This is the edge [`Graph`] adds within the root.
+fn local_target<'a>(key: &'a str, dep: &'a Dep) -> &'a str {
+    match dep.subrepo.as_ref() {
+        Some(s) => local_target(&s.pkg_name, &s.pkg),
+        None => key,
+    }
+}
+
+/// The members of the same root that `member` waits for: one per `deps` entry, see
+/// `local_target`.
+pub fn local_deps(member: &Member) -> BTreeSet<&str> {
+    member
+        .deps
+        .iter()
+        .map(|(key, dep)| local_target(key, dep))
+        .collect()
+}
+
+/// `seeds` plus every member they transitively depend on through `Member::deps`.
+pub fn with_deps(root: &Root, seeds: impl IntoIterator<Item = String>) -> BTreeSet<String> {
+    let mut seen = BTreeSet::new();
+    let mut stack: Vec<String> = seeds.into_iter().collect();
+    while let Some(x) = stack.pop() {
+        if !seen.insert(x.clone()) {
+            continue;
+        }
+        if let Some(m) = root.members.get(&x) {
+            stack.extend(local_deps(m).into_iter().map(str::to_owned));
+        }
+    }
+    seen
+}
+
+/// `seeds` plus every member that transitively depends on one of them.
+pub fn with_dependents(root: &Root, seeds: impl IntoIterator<Item = String>) -> BTreeSet<String> {
+    let mut seen = BTreeSet::new();
+    let mut stack: Vec<String> = seeds.into_iter().collect();
+    while let Some(x) = stack.pop() {
+        if !seen.insert(x.clone()) {
+            continue;
+        }
+        stack.extend(
+            root.members
+                .iter()
+                .filter(|(_, m)| local_deps(m).contains(x.as_str()))
+                .map(|(p, _)| p.clone()),
+        );
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, members};
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn seeds(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn transitive_deps_and_dependents() {
+        // core <- lib <- app, core <- cli, plus an unrelated tool
+        let root = members(&[
+            ("app", &["lib"]),
+            ("cli", &["core"]),
+            ("core", &[]),
+            ("lib", &["core"]),
+            ("tool", &[]),
+        ]);
+        assert_eq!(
+            with_deps(&root, seeds(&["app"])),
+            set(&["app", "core", "lib"])
+        );
+        assert_eq!(with_deps(&root, seeds(&["core"])), set(&["core"]));
+        assert_eq!(
+            with_dependents(&root, seeds(&["core"])),
+            set(&["app", "cli", "core", "lib"])
+        );
+        assert_eq!(
+            with_dependents(&root, seeds(&["lib", "tool"])),
+            set(&["app", "lib", "tool"])
+        );
+        assert_eq!(with_deps(&root, seeds(&[])), set(&[]));
+    }
+
+    /// `a` depends on the private `b` and on `x` of the subroot `p/vendor`.
+    fn sample() -> Graph {
+        let node = |cargo, npm, private, root: &str| Node {
+            cargo,
+            npm,
+            private,
+            root: root.to_owned(),
+        };
+        Graph {
+            edges: BTreeMap::from([
+                ("a".to_owned(), set(&["b", "p/vendor/x"])),
+                ("b".to_owned(), set(&[])),
+                ("p/vendor/x".to_owned(), set(&[])),
+            ]),
+            nodes: BTreeMap::from([
+                ("a".to_owned(), node(true, false, false, "")),
+                ("b".to_owned(), node(false, true, true, "")),
+                (
+                    "p/vendor/x".to_owned(),
+                    node(false, false, false, "p/vendor"),
+                ),
+            ]),
+        }
+    }
+
+    #[test]
+    fn render_dot() {
+        assert_eq!(
+            sample().render(Format::Dot),
+            r#"digraph pupi {
+  "a" [label="a (cargo)"];
+  "b" [label="b (npm, private)", style=dashed];
+  subgraph "cluster_p/vendor" {
+    label="p/vendor";
+    "p/vendor/x" [label="p/vendor/x"];
+  }
+  "a" -> "b";
+  "a" -> "p/vendor/x" [style=dashed];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn render_mermaid() {
+        assert_eq!(
+            sample().render(Format::Mermaid),
+            r#"flowchart LR
+  n0["a (cargo)"]
+  n1["b (npm, private)"]
+  subgraph s1["p/vendor"]
+    n2["p/vendor/x"]
+  end
+  n0 --> n1
+  n0 -.-> n2
+  classDef private stroke-dasharray: 5 5
+  class n1 private
+"#
+        );
+    }
+
+    #[test]
+    fn render_json() {
+        let json: serde_json::Value = serde_json::from_str(&sample().render(Format::Json)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "nodes": {
+                    "a": {"cargo": true, "npm": false, "private": false, "root": ""},
+                    "b": {"cargo": false, "npm": true, "private": true, "root": ""},
+                    "p/vendor/x": {"cargo": false, "npm": false, "private": false, "root": "p/vendor"},
+                },
+                "edges": [
+                    {"from": "a", "to": "b", "subroot": false},
+                    {"from": "a", "to": "p/vendor/x", "subroot": true},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn cycles_in_root() {
+        let d = DepMap::default();
+        let acyclic = members(&[("a", &["b"]), ("b", &["c"]), ("c", &[])]);
+        assert_eq!(Graph::build(&acyclic, ".", &d).unwrap().find_cycle(), None);
+        let cyclic = members(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        assert_eq!(
+            Graph::build(&cyclic, ".", &d).unwrap().find_cycle(),
+            Some(seeds(&["a", "b", "c", "a"]))
+        );
+        let own = members(&[("a", &["a"])]);
+        assert_eq!(
+            Graph::build(&own, ".", &d).unwrap().find_cycle(),
+            Some(seeds(&["a", "a"]))
+        );
+    }
+
+    #[test]
+    fn cycles_across_subroots() {
+        // `q` depends on `x` of the subroot `p/vendor` through `p`'s subtree.
+        let dir = TempDir::new("graph-subroot");
+        dir.write(
+            "p/vendor/pupi.json",
+            r#"{
+                "x": {"deps": {"y": {}}, "version": "1.0.0", "description": ""},
+                "y": {"deps": {}, "version": "1.0.0", "description": ""}
+            }"#,
+        );
+        let root = |p_deps: &str| -> Root {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "p": {{"deps": {p_deps}, "version": "1.0.0", "description": "",
+                           "subtree": {{"paths": {{"vendor": "https://example.com/v main"}}}}}},
+                    "q": {{"deps": {{"x": {{"subrepo": {{"pkg_name": "p", "pkg": {{}},
+                           "subrepo": "vendor", "nest": {{}}}}}}}},
+                           "version": "1.0.0", "description": ""}}
+                }}"#
+            ))
+            .unwrap()
+        };
+        let acyclic = root("{}");
+        let g = Graph::build(&acyclic, dir.path(), &DepMap::default()).unwrap();
+        assert_eq!(g.edges["q"], set(&["p", "p/vendor/x"]));
+        // The scheduler waits for the same in-root edge.
+        assert_eq!(local_deps(&acyclic.members["q"]), BTreeSet::from(["p"]));
+        assert_eq!(g.edges["p/vendor/x"], set(&["p/vendor/y"]));
+        assert_eq!(g.find_cycle(), None);
+        // `p` depending back on `q` closes a cycle through the subrepo dep.
+        let cyclic = root(r#"{"q": {}}"#);
+        let g = Graph::build(&cyclic, dir.path(), &DepMap::default()).unwrap();
+        assert_eq!(g.find_cycle(), Some(seeds(&["p", "q", "p"])));
+        // A cycle inside the subroot is found through the subrepo dep that reaches it.
+        dir.write(
+            "p/vendor/pupi.json",
+            r#"{
+                "x": {"deps": {"y": {}}, "version": "1.0.0", "description": ""},
+                "y": {"deps": {"x": {}}, "version": "1.0.0", "description": ""}
+            }"#,
+        );
+        let g = Graph::build(&acyclic, dir.path(), &DepMap::default()).unwrap();
+        assert_eq!(
+            g.find_cycle(),
+            Some(seeds(&["p/vendor/x", "p/vendor/y", "p/vendor/x"]))
+        );
+    }
+}
@@ -1,81 +1,211 @@
+use cache::Cache;
+use clap::Parser;
+use cli::{Cli, PublishArgs, RunArgs};
+use error::{Error, ErrorKind, Phase, Result};
 use once_cell::sync::OnceCell;
+use output::Output;
+use report::{Outcome, Report};
+use schedule::Limit;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     // cell::OnceCell,
-    borrow::Cow,
     collections::{BTreeMap, BTreeSet},
-    fs::File,
-    io::{ErrorKind, Write, stderr, stdout},
+    io::{Write, stdout},
     path::Path,
-    process::Command,
-    sync::{Mutex, RwLock},
+    process::{Command, ExitCode, Stdio},
+    sync::Mutex,
 };
 
+mod cache;
+mod changelog;
+mod check;
+mod cli;
+pub mod error;
+mod git;
+mod graph;
+mod manifest;
+mod output;
+mod query;
+mod registry;
+mod release;
+mod report;
+mod schedule;
+mod select;
+#[cfg(test)]
+mod test_util;
+mod version;
+
+/// The configuration file `load_config` would read: `{config_name}.json`, `.yaml` or `.yml`,
+/// whichever exists first.
+pub fn config_path(base_path: &str, config_name: &str) -> Option<String> {
+    ["json", "yaml", "yml"]
+        .iter()
+        .map(|ext| format!("{base_path}/{config_name}.{ext}"))
+        .find(|p| Path::new(p).exists())
+}
 /// Load a configuration from either JSON or YAML file.
 /// Checks for JSON first, then YAML. This does NOT apply to package.json.
 ///
 /// # Panics
 /// Panics if `config_name` is "package" as package.json files should not use YAML.
-pub fn load_config<T: for<'de> Deserialize<'de>>(
-    base_path: &str,
-    config_name: &str,
-) -> std::io::Result<T> {
+pub fn load_config<T: for<'de> Deserialize<'de>>(base_path: &str, config_name: &str) -> Result<T> {
     // Enforce that package.json is not affected by YAML support
     assert!(
         config_name != "package",
         "package.json files are not supported by load_config. Use serde_json directly."
     );
 
-    let json_path = format!("{base_path}/{config_name}.json");
-    let yaml_path = format!("{base_path}/{config_name}.yaml");
-    let yml_path = format!("{base_path}/{config_name}.yml");
-
-    if Path::new(&json_path).exists() {
-        let file = File::open(&json_path)?;
-        serde_json::from_reader(file).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-    } else if Path::new(&yaml_path).exists() {
-        let content = std::fs::read_to_string(&yaml_path)?;
-        serde_yml::from_str(&content).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-    } else if Path::new(&yml_path).exists() {
-        let content = std::fs::read_to_string(&yml_path)?;
-        serde_yml::from_str(&content).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-    } else {
-        Err(std::io::Error::new(
-            ErrorKind::NotFound,
-            format!(
-                "Configuration file not found: {config_name}.json, {config_name}.yaml, or {config_name}.yml in {base_path}"
+    let invalid = |path: &str, e: &dyn std::fmt::Display| {
+        Error::from(ErrorKind::Config {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })
+    };
+    let Some(path) = config_path(base_path, config_name) else {
+        return Err(invalid(
+            base_path,
+            &format!(
+                "Configuration file not found: {config_name}.json, {config_name}.yaml, or {config_name}.yml"
             ),
-        ))
-    }
-}
-fn out(c: &mut Command) -> std::io::Result<()> {
-    let o = c.output()?;
-    stdout().write_all(&o.stdout)?;
-    stderr().write_all(&o.stderr)?;
-    if !o.status.success() {
-        eprintln!(
-            "[FAIL] {} ({})@{}",
-            c.get_program().display(),
-            c.get_args()
-                .map(|a| format!("[{}]", a.display()))
-                .collect::<Vec<_>>()
-                .join(" "),
-            c.get_current_dir()
-                .map(|d| format!("{}", d.display()))
-                .unwrap_or_else(|| format!("[[current directory]]"))
-        );
-        std::process::exit(o.status.code().unwrap());
-    }
-    return Ok(());
-}
-fn main() -> std::io::Result<()> {
-    let mut args = std::env::args();
-    args.next();
-    let cmd = args.next().unwrap();
-    match &*cmd {
-        "setup" => {
-            let root_path = args.next().unwrap();
+        ));
+    };
+    let content = std::fs::read_to_string(&path)?;
+    if path.ends_with(".json") {
+        serde_json::from_str(&content).map_err(|e| invalid(&path, &e))
+    } else {
+        serde_yml::from_str(&content).map_err(|e| invalid(&path, &e))
+    }
+}
+/// Read a member manifest, reporting a missing file as [`ErrorKind::MissingManifest`].
+fn read_manifest(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => ErrorKind::MissingManifest {
+            path: path.to_owned(),
+        }
+        .into(),
+        _ => e.into(),
+    })
+}
+fn read_json(path: &str) -> Result<serde_json::Value> {
+    serde_json::from_str(&read_manifest(path)?).map_err(|e| {
+        ErrorKind::Manifest {
+            path: path.to_owned(),
+            message: e.to_string(),
+        }
+        .into()
+    })
+}
+fn read_toml(path: &str) -> Result<toml_edit::DocumentMut> {
+    read_manifest(path)?
+        .parse()
+        .map_err(|e: toml_edit::TomlError| {
+            ErrorKind::Manifest {
+                path: path.to_owned(),
+                message: e.to_string(),
+            }
+            .into()
+        })
+}
+/// Flags shared by every member of a run.
+struct Options {
+    /// Report planned manifest writes and commands instead of performing them.
+    dry_run: bool,
+    /// Caps concurrent external tools across all workers.
+    tools: Limit,
+    /// Where child process output goes.
+    output: Output,
+    /// `pupi publish` flags; defaults for other commands.
+    publish: PublishArgs,
+    /// Held while uploading a member and waiting for it to resolve, so that members are
+    /// published one at a time in dependency order.
+    uploads: Mutex<()>,
+}
+fn describe(c: &Command) -> String {
+    format!(
+        "{} ({})@{}",
+        c.get_program().display(),
+        c.get_args()
+            .map(|a| format!("[{}]", a.display()))
+            .collect::<Vec<_>>()
+            .join(" "),
+        c.get_current_dir()
+            .map(|d| format!("{}", d.display()))
+            .unwrap_or_else(|| "[[current directory]]".to_owned())
+    )
+}
+/// Run `c` for member `xpath`, forwarding its output line by line through [`Options::output`].
+fn out(opts: &Options, xpath: &str, phase: Phase, c: &mut Command) -> Result<()> {
+    if opts.dry_run {
+        println!("[DRY] {}", describe(c));
+        return Ok(());
+    }
+    let (status, o, e) = {
+        let _slot = opts.tools.acquire();
+        let mut child = c.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let (so, se) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+        let (o, e) = std::thread::scope(|s| {
+            let o = s.spawn(|| opts.output.pump(so, xpath, phase, false));
+            let e = opts.output.pump(se, xpath, phase, true);
+            (o.join().unwrap(), e)
+        });
+        (child.wait()?, o, e)
+    };
+    if !status.success() {
+        let command = describe(c);
+        return Err(if c.get_program() == "git" {
+            ErrorKind::Git {
+                command,
+                status,
+                stderr: e,
+            }
+        } else {
+            ErrorKind::Command {
+                command,
+                status,
+                stdout: o,
+                stderr: e,
+            }
+        }
+        .into());
+    }
+    Ok(())
+}
+/// Write `contents` to `path`, or print the diff against the current file in dry-run mode.
+///
+/// Files whose contents would not change are left untouched.
+fn write(opts: &Options, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+    let contents = contents.as_ref();
+    let old = std::fs::read(path).unwrap_or_default();
+    if old == contents {
+        return Ok(());
+    }
+    if !opts.dry_run {
+        return Ok(std::fs::write(path, contents)?);
+    }
+    let old = String::from_utf8_lossy(&old);
+    let new = String::from_utf8_lossy(contents);
+    let diff = similar::TextDiff::from_lines(&*old, &*new)
+        .unified_diff()
+        .header(path, path)
+        .to_string();
+    stdout().lock().write_all(diff.as_bytes())?;
+    Ok(())
+}
+pub fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e.render());
+            ExitCode::FAILURE
+        }
+    }
+}
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+    let cmd = cli.command.name();
+    match cli.command {
+        cli::Command::Setup { root_path } => {
             if !std::fs::exists(format!("{root_path}/.git"))? {
                 std::process::Command::new("git")
                     .arg("init")
@@ -88,6 +218,7 @@ fn main() -> std::io::Result<()> {
                 /target
                 node_modules
                 .parcel-cache
+                .pupi
                 "#,
                 )?;
             }
@@ -124,85 +255,150 @@ fn main() -> std::io::Result<()> {
                 .spawn()?
                 .wait()?;
         }
-        "schema" => {
+        cli::Command::Schema => {
             // Generate JSON schema for Root configuration
             let schema = schemars::generate::SchemaSettings::default()
                 .into_generator()
                 .into_root_schema_for::<Root>();
-            let schema_json = serde_json::to_string_pretty(&schema)
-                .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
+            let schema_json =
+                serde_json::to_string_pretty(&schema).map_err(std::io::Error::other)?;
             println!("{}", schema_json);
         }
-        // "subtree" => {
-        //     let root_path = args.next().unwrap();
-        //     let args = args.collect::<Vec<_>>();
-        //     let root: Root =
-        //         serde_json::from_reader(File::open(format!("{root_path}/pupi.json"))?)?;
-        //     // let visited = RwLock::new(BTreeSet::new());
-        //     let mut error = OnceCell::new();
-        //     let roots: Mutex<BTreeMap<String, Root>> = Mutex::new(BTreeMap::new());
-        //     std::thread::scope(|s| {
-        //         for (path, member) in root.members.iter() {
-        //             // });
-        //         }
-        //     });
-        //     if let Some(e) = error.take() {
-        //         return Err(e);
-        //     }
-        //     let mut root = root;
-        //     for (k, r) in roots.into_inner().unwrap() {
-        //         for (p, m) in r.members {
-        //             root.members.insert(format!("{k}/{p}").replace("./", ""), m);
-        //         }
-        //     }
-        //     std::fs::write(
-        //         format!("{root_path}/pupi.json"),
-        //         serde_json::to_vec_pretty(&root)?,
-        //     )?;
-        // }
-        _ => {
-            let root_path = args.next().unwrap();
-            let args = args.collect::<Vec<_>>();
+        cli::Command::Check { root_path } => {
+            let root: Root = load_config(&root_path, "pupi")?;
+            check::validate(&root, &root_path, &DepMap::default())
+                .map_err(|e| e.in_phase(Phase::Config))?;
+            eprintln!("[Check] {} member(s) OK", root.members.len());
+        }
+        cli::Command::Graph { root_path, format } => {
             let root: Root = load_config(&root_path, "pupi")?;
-            let visited = RwLock::new(BTreeSet::new());
-            let mut error = OnceCell::new();
+            let graph = graph::Graph::build(&root, &root_path, &DepMap::default())?;
+            print!("{}", graph.render(format));
+        }
+        cli::Command::Query(args) => {
+            let root: Root = load_config(&args.root_path, "pupi")?;
+            for line in query::query(&root, &args)? {
+                println!("{line}");
+            }
+        }
+        cli::Command::Version(args) => {
+            let root: Root = load_config(&args.root_path, "pupi")?;
             let d = DepMap::default();
-            add_workspaces(&root, &root_path)?;
-            std::thread::scope(|s| {
-                for (path, member) in root.members.iter() {
-                    // let path = format!("{root_path}/{path}");
-                    s.spawn(|| {
-                        match update(UpdateContext {
-                            xpath: path,
-                            root_path: &root_path,
-                            member,
-                            root: &root,
-                            visited: &visited,
-                            depmap: &d,
-                            cmd: &[cmd.clone()]
-                                .into_iter()
-                                .chain(args.clone())
-                                .collect::<Vec<_>>(),
-                        }) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                let _ = error.set(e);
-                            }
-                        }
-                    });
-                }
-            });
-            if let Some(e) = error.take() {
-                return Err(e);
+            let tags = git::Tags::new(&root, &args.root_path, &d);
+            let bumps = match (args.member, args.bump) {
+                (Some(member), Some(bump)) => BTreeMap::from([(member, bump)]),
+                _ => version::auto(&root, &args.root_path, &tags)?,
+            };
+            let versions = version::plan(&root, &args.root_path, &bumps)?;
+            if versions.is_empty() {
+                eprintln!("[Version] nothing to release");
             }
+            for (xpath, v) in versions.iter() {
+                eprintln!("[Version] {xpath} {} -> {v}", root.members[xpath].version);
+            }
+            let opts = Options {
+                dry_run: args.dry_run,
+                tools: Limit::new(1),
+                output: Output::default(),
+                publish: PublishArgs::default(),
+                uploads: Mutex::new(()),
+            };
+            version::save(&opts, &args.root_path, &versions)?;
+            changelog::update(&opts, &root, &args.root_path, &tags, &versions)?;
         }
+        cli::Command::Build(run)
+        | cli::Command::Test(run)
+        | cli::Command::Autogen(run)
+        | cli::Command::Update(run) => members(cmd, run, PublishArgs::default())?,
+        cli::Command::Publish { run, publish } => members(cmd, run, publish)?,
     }
     Ok(())
 }
-fn add_workspaces(root: &Root, root_path: &str) -> std::io::Result<()> {
+/// Run `cmd` over the selected members of a workspace in dependency order.
+fn members(cmd: &str, run: RunArgs, publish: PublishArgs) -> Result<()> {
+    let root_path = run.root_path;
+    let root: Root = load_config(&root_path, "pupi")?;
+    let report = Report::new(run.keep_going);
+    let d = DepMap::default();
+    let cmd = [cmd.to_owned()]
+        .into_iter()
+        .chain(run.args)
+        .collect::<Vec<_>>();
+    let selected = select::select(&root, &root_path, &run.select)?;
+    check::validate(&root, &root_path, &d).map_err(|e| e.in_phase(Phase::Config))?;
+    if publish.release {
+        release::ensure_clean(&root_path).map_err(|e| e.in_phase(Phase::Release))?;
+    }
+    let jobs = run.jobs.unwrap_or_else(schedule::default_jobs);
+    let opts = Options {
+        dry_run: run.dry_run,
+        tools: Limit::new(run.tool_jobs.unwrap_or(jobs)),
+        output: Output::new(run.output),
+        publish,
+        uploads: Mutex::new(()),
+    };
+    add_workspaces(&opts, &root, &root_path, &d).map_err(|e| e.in_phase(Phase::Workspace))?;
+    sync_cargo(&opts, &root, &root_path, &d).map_err(|e| e.in_phase(Phase::Workspace))?;
+    let cache = (cmd[0] == "build").then(|| Cache::load(&root_path, run.force));
+    schedule::run(&root, jobs, |path| {
+        update(UpdateContext {
+            xpath: path,
+            root_path: &root_path,
+            member: &root.members[path],
+            root: &root,
+            selected: &selected,
+            depmap: &d,
+            cmd: &cmd,
+            opts: &opts,
+            report: &report,
+            cache: cache.as_ref(),
+        });
+        opts.output.flush(path);
+    });
+    if let Some(cache) = cache.as_ref()
+        && !opts.dry_run
+    {
+        cache.save().map_err(|e| e.in_phase(Phase::Cache))?;
+    }
+    let publishable = |x: &String| {
+        let m = &root.members[x];
+        !m.private && (m.cargo.is_some() || m.npm.is_some())
+    };
+    let succeeded = report.succeeded();
+    let published = succeeded
+        .iter()
+        .filter(|x| publishable(x))
+        .cloned()
+        .collect::<Vec<_>>();
+    let finished = report.finish(&selected);
+    if cmd[0] == "publish" {
+        let unpublished = selected
+            .iter()
+            .filter(|x| publishable(x) && !succeeded.contains(*x))
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>();
+        if !unpublished.is_empty() {
+            eprintln!(
+                "[Publish] {} member(s) not published: {}",
+                unpublished.len(),
+                unpublished.join(", ")
+            );
+        }
+    }
+    // A failed run is not released, so that re-running it once fixed commits and tags everything.
+    if opts.publish.release && finished.is_ok() {
+        let released = release::release(&opts, &root, &root_path, &d, &published);
+        // `git commit`/`git tag` output is grouped under the root, not under a member.
+        opts.output.flush(&root_path);
+        released.map_err(|e| e.in_phase(Phase::Release))?;
+    } else if opts.publish.release {
+        eprintln!("[Release] Skipped: not every member succeeded");
+    }
+    finished
+}
+fn add_workspaces(opts: &Options, root: &Root, root_path: &str, d: &DepMap) -> Result<()> {
     if std::fs::exists(format!("{root_path}/package.json"))? {
-        let mut val: serde_json::Value =
-            serde_json::from_reader(File::open(format!("{root_path}/package.json"))?)?;
+        let mut val = read_json(&format!("{root_path}/package.json"))?;
         if let Some(o) = val.as_object_mut() {
             let w = o.get("workspaces").and_then(|a| a.as_array());
             o.insert(
@@ -210,10 +406,7 @@ fn add_workspaces(root: &Root, root_path: &str) -> std::io::Result<()> {
                 serde_json::Value::Array(
                     root.members
                         .iter()
-                        .filter_map(|(a, b)| match b.npm.as_ref() {
-                            None => None,
-                            Some(_) => Some(a.clone()),
-                        })
+                        .filter_map(|(a, b)| b.npm.as_ref().map(|_| a.clone()))
                         .chain(
                             w.iter()
                                 .flat_map(|a| a.iter())
@@ -224,56 +417,69 @@ fn add_workspaces(root: &Root, root_path: &str) -> std::io::Result<()> {
                             while let Some(b) = a.strip_prefix("./") {
                                 a = b.to_owned();
                             }
-                            return a;
+                            a
                         })
                         .collect::<BTreeSet<_>>()
                         .into_iter()
-                        .map(|a| serde_json::Value::String(a))
+                        .map(serde_json::Value::String)
                         .collect(),
                 ),
             );
         }
-        std::fs::write(
-            format!("{root_path}/package.json"),
-            serde_json::to_vec_pretty(&val)?,
+        write(
+            opts,
+            &format!("{root_path}/package.json"),
+            manifest::json(&val, &format!("{root_path}/package.json"))?,
         )?;
     }
     if std::fs::exists(format!("{root_path}/Cargo.toml"))? {
-        let mut val: toml::Table = std::fs::read_to_string(format!("{root_path}/Cargo.toml"))?
-            .parse()
-            .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
+        let mut val = read_toml(&format!("{root_path}/Cargo.toml"))?;
         if let Some(m) = val
             .get_mut("workspace")
-            .and_then(|a| a.as_table_mut())
+            .and_then(|a| a.as_table_like_mut())
             .and_then(|a| a.get_mut("members"))
             .and_then(|a| a.as_array_mut())
         {
-            *m = root
+            let members = root
                 .members
                 .iter()
-                .filter_map(|(a, b)| match b.cargo.as_ref() {
-                    None => None,
-                    Some(_) => Some(a.clone()),
-                })
-                .chain(
-                    m.into_iter()
-                        .filter_map(|a| a.as_str().map(|a| a.to_owned())),
-                )
+                .filter_map(|(a, b)| b.cargo.as_ref().map(|_| a.clone()))
+                .chain(m.iter().filter_map(|a| a.as_str().map(|a| a.to_owned())))
                 .map(|mut a| {
                     while let Some(b) = a.strip_prefix("./") {
                         a = b.to_owned();
                     }
-                    return a;
+                    a
                 })
-                .collect::<BTreeSet<_>>()
-                .into_iter()
-                .map(|a| toml::Value::String(a))
-                .collect();
+                .collect::<BTreeSet<_>>();
+            manifest::set_strings(m, members);
         }
-        std::fs::write(
-            format!("{root_path}/Cargo.toml"),
-            toml::to_string_pretty(&val).map_err(|e| std::io::Error::new(ErrorKind::Other, e))?,
-        )?;
+        let rcargo = d.rcargo(root, root_path)?;
+        manifest::set_dep_versions(&mut val, |name| {
+            Some(root.members.get(rcargo.get(name)?)?.version.clone())
+        });
+        write(opts, &format!("{root_path}/Cargo.toml"), val.to_string())?;
+    }
+    Ok(())
+}
+/// Sync the package fields and local dependency versions of every Cargo member's manifest.
+///
+/// Done for all members before any `cargo` runs: `cargo` resolves the whole workspace, so one
+/// member still requiring the old version of another would break every member's build.
+fn sync_cargo(opts: &Options, root: &Root, root_path: &str, d: &DepMap) -> Result<()> {
+    let rcargo = d.rcargo(root, root_path)?;
+    for (xpath, member) in root.members.iter().filter(|(_, m)| m.cargo.is_some()) {
+        let path = format!("{root_path}/{xpath}/Cargo.toml");
+        let mut val = read_toml(&path)?;
+        if let Some(p) = val.get_mut("package").and_then(|a| a.as_table_like_mut()) {
+            manifest::set(p, "version", &member.version);
+            manifest::set(p, "description", &member.description);
+            manifest::set(p, "publish", !member.private);
+        }
+        manifest::set_dep_versions(&mut val, |name| {
+            Some(root.members.get(rcargo.get(name)?)?.version.clone())
+        });
+        write(opts, &path, val.to_string())?;
     }
     Ok(())
 }
@@ -282,9 +488,13 @@ struct UpdateContext<'a> {
     root_path: &'a str,
     member: &'a Member,
     root: &'a Root,
-    visited: &'a RwLock<BTreeSet<String>>,
+    selected: &'a BTreeSet<String>,
     depmap: &'a DepMap,
     cmd: &'a [String],
+    opts: &'a Options,
+    report: &'a Report,
+    /// Build fingerprints; only set for `pupi build`.
+    cache: Option<&'a Cache>,
 }
 
 struct BuildContext<'a> {
@@ -295,166 +505,160 @@ struct BuildContext<'a> {
     root: &'a Root,
     depmap: &'a DepMap,
     cmd: &'a [String],
+    opts: &'a Options,
     update: bool,
+    /// The member's inputs are unchanged since its last successful build.
+    fresh: bool,
+}
+impl BuildContext<'_> {
+    /// Whether another member of this root depends on this one, and so has to wait until it
+    /// is resolvable in the registry before publishing.
+    fn has_dependents(&self) -> bool {
+        self.root
+            .members
+            .values()
+            .any(|m| m.deps.contains_key(self.xpath))
+    }
 }
 
 #[derive(Default)]
 struct DepMap {
     npm: OnceCell<BTreeMap<String, String>>,
     rnpm: OnceCell<BTreeMap<String, String>>,
+    cargo: OnceCell<BTreeMap<String, String>>,
+    rcargo: OnceCell<BTreeMap<String, String>>,
     subroots: OnceCell<BTreeMap<String, SubrootEntry>>,
 }
 #[derive(Default)]
 struct SubrootEntry {
     root: OnceCell<Root>,
-    members: RwLock<BTreeSet<String>>,
     depmap: DepMap,
 }
+type Subroot<'a> = (&'a Root, String, &'a DepMap);
 impl DepMap {
-    fn subroot(
-        &self,
-        root: &Root,
-        root_path: &str,
-        name: &str,
-    ) -> std::io::Result<Option<(&Root, &RwLock<BTreeSet<String>>, String, &DepMap)>> {
+    fn subroot(&self, root: &Root, root_path: &str, name: &str) -> Result<Option<Subroot<'_>>> {
         let m = self.subroots.get_or_try_init(|| {
-            Ok::<_, std::io::Error>(
+            Ok::<_, Error>(
                 root.members
                     .iter()
                     .flat_map(|(a, b)| {
                         b.subtree
                             .iter()
-                            .flat_map(move |a2| {
-                                a2.paths.iter().map(move |(p, _)| format!("{a}/{p}"))
-                            })
+                            .flat_map(move |a2| a2.paths.keys().map(move |p| format!("{a}/{p}")))
                             .chain(b.submodule.iter().flat_map(move |a2| {
-                                a2.paths.iter().map(move |(p, _)| format!("{a}/{p}"))
+                                a2.paths.keys().map(move |p| format!("{a}/{p}"))
                             }))
                     })
                     .map(|a| (a, Default::default()))
                     .collect(),
             )
         })?;
-        let Some(SubrootEntry {
-            root: m,
-            members: r,
-            depmap: n,
-        }) = m.get(name)
-        else {
+        let Some(SubrootEntry { root: m, depmap: n }) = m.get(name) else {
             return Ok(None);
         };
         let m = m.get_or_try_init(|| {
             let subroot_path = format!("{root_path}/{name}");
             let root: Root = load_config(&subroot_path, "pupi")?;
-            Ok::<_, std::io::Error>(root)
+            Ok::<_, Error>(root)
         })?;
-        return Ok(Some((m, r, format!("{root_path}/{name}"), n)));
+        Ok(Some((m, format!("{root_path}/{name}"), n)))
     }
-    fn npm(&self, root: &Root, root_path: &str) -> std::io::Result<&BTreeMap<String, String>> {
-        return self.npm.get_or_try_init(|| {
+    fn npm(&self, root: &Root, root_path: &str) -> Result<&BTreeMap<String, String>> {
+        self.npm.get_or_try_init(|| {
             let mut m: BTreeMap<String, String> = BTreeMap::new();
             for (a, b) in root.members.iter() {
-                if let Some(_) = b.npm.as_ref() {
-                    let mut val: serde_json::Value = serde_json::from_reader(File::open(
-                        format!("{root_path}/{a}/package.json"),
-                    )?)?;
-                    let name = val
-                        .as_object()
-                        .unwrap()
-                        .get("name")
-                        .unwrap()
-                        .as_str()
-                        .unwrap();
+                if b.npm.is_some() {
+                    let path = format!("{root_path}/{a}/package.json");
+                    let val = read_json(&path)?;
+                    let Some(name) = val.get("name").and_then(|n| n.as_str()) else {
+                        return Err(ErrorKind::Manifest {
+                            path,
+                            message: "missing `name`".to_owned(),
+                        }
+                        .into());
+                    };
                     m.insert(a.clone(), name.to_owned());
                 }
             }
-            return Ok(m);
-        });
+            Ok(m)
+        })
     }
-    fn rnpm(&self, root: &Root, root_path: &str) -> std::io::Result<&BTreeMap<String, String>> {
-        return self.rnpm.get_or_try_init(|| {
+    fn rnpm(&self, root: &Root, root_path: &str) -> Result<&BTreeMap<String, String>> {
+        self.rnpm.get_or_try_init(|| {
             Ok(self
                 .npm(root, root_path)?
                 .iter()
                 .map(|(a, b)| (b.clone(), a.clone()))
                 .collect())
-        });
+        })
     }
-}
-fn update_dep(ctx: UpdateContext, dep: &Dep) -> std::io::Result<()> {
-    let mut root_path = Cow::Borrowed(ctx.root_path);
-    let mut root = ctx.root;
-    let mut visited = ctx.visited;
-    let mut depmap = ctx.depmap;
-    let mut dep = dep;
-    let do_update = matches!(&*ctx.cmd[0], "autogen" | "build" | "publish" | "update");
-    loop {
-        if let Some(s) = dep.subrepo.as_ref() {
-            if do_update {
-                return update(UpdateContext {
-                    xpath: ctx.xpath,
-                    root_path: &root_path,
-                    member: ctx.member,
-                    root,
-                    visited,
-                    depmap,
-                    cmd: ctx.cmd,
-                });
-            }
-            update_dep(
-                UpdateContext {
-                    xpath: &s.pkg_name,
-                    root_path: &root_path,
-                    member: ctx.member,
-                    root,
-                    visited,
-                    depmap,
-                    cmd: ctx.cmd,
-                },
-                &s.pkg,
-            )?;
-            if let Some((a, b, c, d)) =
-                depmap.subroot(root, &root_path, &format!("{}/{}", &s.pkg_name, &s.subrepo))?
-            {
-                root_path = Cow::Owned(c);
-                root = a;
-                visited = b;
-                depmap = d;
-                dep = &s.nest;
-                continue;
+    /// Crate names of the Cargo members, by xpath.
+    fn cargo(&self, root: &Root, root_path: &str) -> Result<&BTreeMap<String, String>> {
+        self.cargo.get_or_try_init(|| {
+            let mut m: BTreeMap<String, String> = BTreeMap::new();
+            for (a, b) in root.members.iter() {
+                if b.cargo.is_some() {
+                    let path = format!("{root_path}/{a}/Cargo.toml");
+                    let val = read_toml(&path)?;
+                    let Some(name) = val
+                        .get("package")
+                        .and_then(|p| p.get("name"))
+                        .and_then(|n| n.as_str())
+                    else {
+                        return Err(ErrorKind::Manifest {
+                            path,
+                            message: "missing `package.name`".to_owned(),
+                        }
+                        .into());
+                    };
+                    m.insert(a.clone(), name.to_owned());
+                }
             }
-        }
-        return update(UpdateContext {
-            xpath: ctx.xpath,
-            root_path: &root_path,
-            member: ctx.member,
-            root,
-            visited,
-            depmap,
-            cmd: ctx.cmd,
-        });
+            Ok(m)
+        })
+    }
+    fn rcargo(&self, root: &Root, root_path: &str) -> Result<&BTreeMap<String, String>> {
+        self.rcargo.get_or_try_init(|| {
+            Ok(self
+                .cargo(root, root_path)?
+                .iter()
+                .map(|(a, b)| (b.clone(), a.clone()))
+                .collect())
+        })
     }
 }
-fn update(ctx: UpdateContext) -> std::io::Result<()> {
-    if ctx.visited.read().unwrap().contains(ctx.xpath) {
-        return Ok(());
+/// Process a single member. Runs on a scheduler worker once all of its deps have finished.
+fn update(ctx: UpdateContext) {
+    let path = format!("{}/{}", ctx.root_path, ctx.xpath);
+    let update = matches!(
+        &*ctx.cmd[0],
+        "autogen" | "build" | "publish" | "test" | "update"
+    );
+    if let Some(d) = ctx.report.failed_dep(graph::local_deps(ctx.member)) {
+        let why = format!("dependency `{d}` did not succeed");
+        ctx.report.record(ctx.xpath, Outcome::Skipped(why));
+        return;
     }
-    match ctx.visited.write().unwrap() {
-        mut w => {
-            // let mut w = ;
-            if w.contains(ctx.xpath) {
-                return Ok(());
-            }
-            w.insert(ctx.xpath.to_owned());
-        }
+    // Unselected members still get an outcome so that failures propagate through them.
+    if !ctx.selected.contains(ctx.xpath) {
+        ctx.report.record(ctx.xpath, Outcome::Unselected);
+        return;
+    }
+    if ctx.report.cancelled() {
+        ctx.report.record(ctx.xpath, Outcome::Cancelled);
+        return;
+    }
+    let errors = Mutex::new(vec![]);
+    let fail = |e: Error, phase: Phase| {
+        errors
+            .lock()
+            .unwrap()
+            .extend(e.at(ctx.xpath, phase).flatten())
     };
-    let path = format!("{}/{}", ctx.root_path, ctx.xpath);
-    let update = matches!(&*ctx.cmd[0], "autogen" | "build" | "publish" | "update");
-    let mut error = OnceCell::new();
     std::thread::scope(|s| {
         if let Some(subtree) = ctx.member.subtree.as_ref() {
             s.spawn(|| {
-                match subtree.process(BuildContext {
+                if let Err(e) = subtree.process(BuildContext {
                     path: &path,
                     root_path: ctx.root_path,
                     xpath: ctx.xpath,
@@ -462,18 +666,17 @@ fn update(ctx: UpdateContext) -> std::io::Result<()> {
                     root: ctx.root,
                     depmap: ctx.depmap,
                     cmd: ctx.cmd,
+                    opts: ctx.opts,
                     update,
+                    fresh: false,
                 }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        let _ = error.set(e);
-                    }
+                    fail(e, Phase::Subtree);
                 }
             });
         }
         if let Some(submodule) = ctx.member.submodule.as_ref() {
             s.spawn(|| {
-                match submodule.process(BuildContext {
+                if let Err(e) = submodule.process(BuildContext {
                     path: &path,
                     root_path: ctx.root_path,
                     xpath: ctx.xpath,
@@ -481,66 +684,71 @@ fn update(ctx: UpdateContext) -> std::io::Result<()> {
                     root: ctx.root,
                     depmap: ctx.depmap,
                     cmd: ctx.cmd,
+                    opts: ctx.opts,
                     update,
+                    fresh: false,
                 }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        let _ = error.set(e);
-                    }
+                    fail(e, Phase::Submodule);
                 }
             });
         }
     });
-    if let Some(e) = error.take() {
-        return Err(e);
-    }
-    std::thread::scope(|s| {
-        for (dep, x) in ctx.member.deps.iter() {
-            let error = &error;
-            s.spawn(move || {
-                match update_dep(
-                    UpdateContext {
-                        xpath: &dep,
-                        root_path: ctx.root_path,
-                        member: ctx.root.members.get(dep).unwrap(),
-                        root: ctx.root,
-                        visited: ctx.visited,
-                        depmap: ctx.depmap,
-                        cmd: ctx.cmd,
-                    },
-                    &x,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        let _ = error.set(e);
-                    }
-                }
-            });
-        }
-    });
-    if let Some(e) = error.take() {
-        return Err(e);
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        ctx.report.record(ctx.xpath, Outcome::Failed(errors));
+        return;
     }
     eprintln!("[Build] Building {}", ctx.xpath);
 
     if let Some(u) = ctx.member.updater.as_ref() {
         match &*ctx.cmd[0] {
-            "autogen" | "build" | "publish" => {
-                out(std::process::Command::new("sh")
-                    .arg(format!("{path}/{}", &u[0]))
-                    .arg(ctx.root_path)
-                    .arg(ctx.xpath)
-                    .args(u[1..].iter())
-                    .args(ctx.cmd.iter())
-                    .current_dir(&path))?;
+            "autogen" | "build" | "publish" | "test" => {
+                if let Err(e) = out(
+                    ctx.opts,
+                    ctx.xpath,
+                    Phase::Updater,
+                    std::process::Command::new("sh")
+                        .arg(format!("{path}/{}", &u[0]))
+                        .arg(ctx.root_path)
+                        .arg(ctx.xpath)
+                        .args(u[1..].iter())
+                        .args(ctx.cmd.iter())
+                        .current_dir(&path),
+                ) {
+                    let errors = e.at(ctx.xpath, Phase::Updater).flatten();
+                    ctx.report.record(ctx.xpath, Outcome::Failed(errors));
+                    return;
+                }
             }
             _ => {}
         }
     }
+    let fresh = match ctx
+        .cache
+        .map(|c| c.fresh(ctx.root, ctx.root_path, ctx.xpath))
+    {
+        Some(Ok(fresh)) => fresh,
+        Some(Err(e)) => {
+            let errors = e.at(ctx.xpath, Phase::Cache).flatten();
+            ctx.report.record(ctx.xpath, Outcome::Failed(errors));
+            return;
+        }
+        None => false,
+    };
+    if fresh {
+        eprintln!("[Build] {} is up to date", ctx.xpath);
+    }
+    let errors = Mutex::new(vec![]);
+    let fail = |e: Error, phase: Phase| {
+        errors
+            .lock()
+            .unwrap()
+            .extend(e.at(ctx.xpath, phase).flatten())
+    };
     std::thread::scope(|s| {
         if let Some(cargo) = ctx.member.cargo.as_ref() {
             s.spawn(|| {
-                match cargo.process(BuildContext {
+                if let Err(e) = cargo.process(BuildContext {
                     path: &path,
                     root_path: ctx.root_path,
                     xpath: ctx.xpath,
@@ -548,18 +756,17 @@ fn update(ctx: UpdateContext) -> std::io::Result<()> {
                     root: ctx.root,
                     depmap: ctx.depmap,
                     cmd: ctx.cmd,
+                    opts: ctx.opts,
                     update,
+                    fresh,
                 }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        let _ = error.set(e);
-                    }
+                    fail(e, Phase::Cargo);
                 }
             });
         }
         if let Some(npm) = ctx.member.npm.as_ref() {
             s.spawn(|| {
-                match npm.process(BuildContext {
+                if let Err(e) = npm.process(BuildContext {
                     path: &path,
                     root_path: ctx.root_path,
                     xpath: ctx.xpath,
@@ -567,20 +774,30 @@ fn update(ctx: UpdateContext) -> std::io::Result<()> {
                     root: ctx.root,
                     depmap: ctx.depmap,
                     cmd: ctx.cmd,
+                    opts: ctx.opts,
                     update,
+                    fresh,
                 }) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        let _ = error.set(e);
-                    }
+                    fail(e, Phase::Npm);
                 }
             });
         }
     });
-    if let Some(e) = error.take() {
-        return Err(e);
+    let mut errors = errors.into_inner().unwrap();
+    if let Some(cache) = ctx.cache
+        && errors.is_empty()
+        && let Err(e) = cache.record(ctx.root, ctx.root_path, ctx.xpath)
+    {
+        errors.extend(e.at(ctx.xpath, Phase::Cache).flatten());
     }
-    Ok(())
+    ctx.report.record(
+        ctx.xpath,
+        if errors.is_empty() {
+            Outcome::Succeeded
+        } else {
+            Outcome::Failed(errors)
+        },
+    );
 }
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 pub struct Root {
@@ -590,7 +807,24 @@ pub struct Root {
     pub members: BTreeMap<String, Member>,
 }
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
-pub struct RootCore {}
+pub struct RootCore {
+    /// Which dependents `pupi version` bumps along with a member.
+    #[serde(default)]
+    pub cascade: version::Cascade,
+    /// Maintain CHANGELOG.md files on `pupi version`; enabled when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<changelog::Changelog>,
+    /// Release tag name with `{name}`, `{version}` and `{xpath}` placeholders, e.g.
+    /// `{name}@{version}` [default: `{xpath}-v{version}`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_format: Option<String>,
+    /// Publish settings for every Cargo member, unless the member overrides them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo: Option<registry::Registry>,
+    /// Publish settings for every npm member, unless the member overrides them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub npm: Option<registry::Registry>,
+}
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 #[non_exhaustive]
 pub struct Member {
@@ -614,10 +848,72 @@ pub struct Member {
 }
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 #[non_exhaustive]
-pub struct Cargo {}
+pub struct Cargo {
+    #[serde(flatten)]
+    pub registry: registry::Registry,
+}
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[non_exhaustive]
+pub struct NPM {
+    #[serde(flatten)]
+    pub registry: registry::Registry,
+    /// How dependencies on other members are written, per package.json section.
+    #[serde(default)]
+    pub ranges: Ranges,
+}
+/// The range written for a dependency on another member at version `1.2.3`.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Range {
+    /// `^1.2.3`
+    #[default]
+    Caret,
+    /// `~1.2.3`
+    Tilde,
+    /// `1.2.3`
+    Exact,
+    /// `workspace:*`. Only allowed for `devDependencies`, or any section of a private member:
+    /// `npm publish` uploads it unresolved, which breaks installs of the other sections but not
+    /// of dev dependencies, which consumers never install.
+    #[serde(rename = "workspace:*")]
+    Workspace,
+}
+impl Range {
+    pub fn format(self, version: &str) -> String {
+        match self {
+            Range::Caret => format!("^{version}"),
+            Range::Tilde => format!("~{version}"),
+            Range::Exact => version.to_owned(),
+            Range::Workspace => "workspace:*".to_owned(),
+        }
+    }
+}
+/// A [`Range`] per package.json dependency section; unset sections use `caret`.
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
 #[non_exhaustive]
-pub struct NPM {}
+pub struct Ranges {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dependencies: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev_dependencies: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_dependencies: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional_dependencies: Option<Range>,
+}
+impl Ranges {
+    /// Each package.json dependency section with the range to write in it.
+    fn sections(&self) -> [(&'static str, Range); 4] {
+        [
+            ("dependencies", self.dependencies),
+            ("devDependencies", self.dev_dependencies),
+            ("peerDependencies", self.peer_dependencies),
+            ("optionalDependencies", self.optional_dependencies),
+        ]
+        .map(|(s, r)| (s, r.unwrap_or_default()))
+    }
+}
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 #[non_exhaustive]
 pub struct Subtree {
@@ -630,59 +926,90 @@ pub struct Submodule {
 }
 
 trait BuildSystem {
-    fn process(&self, ctx: BuildContext) -> std::io::Result<()>;
+    fn process(&self, ctx: BuildContext) -> Result<()>;
 }
 
 impl BuildSystem for Cargo {
-    fn process(&self, ctx: BuildContext) -> std::io::Result<()> {
-        let mut val: toml::Table = std::fs::read_to_string(format!("{}/Cargo.toml", ctx.path))?
-            .parse()
-            .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
-        if ctx.update {
-            if let Some(p) = val.get_mut("package").and_then(|a| a.as_table_mut()) {
-                p.insert(
-                    "version".to_owned(),
-                    toml::Value::String(ctx.member.version.clone()),
-                );
-                p.insert(
-                    "description".to_owned(),
-                    toml::Value::String(ctx.member.description.clone()),
-                );
-                p.insert(
-                    "publish".to_owned(),
-                    toml::Value::Boolean(!ctx.member.private),
-                );
-            }
-        }
+    fn process(&self, ctx: BuildContext) -> Result<()> {
+        // The manifest was already synced by `sync_cargo`.
         match &*ctx.cmd[0] {
+            "build" if ctx.fresh => {}
             "build" | "publish" => {
-                out(std::process::Command::new("cargo")
-                    .arg("check")
-                    .current_dir(&ctx.path))?;
+                out(
+                    ctx.opts,
+                    ctx.xpath,
+                    Phase::Cargo,
+                    std::process::Command::new("cargo")
+                        .arg("check")
+                        .current_dir(ctx.path),
+                )?;
+            }
+            "test" => {
+                out(
+                    ctx.opts,
+                    ctx.xpath,
+                    Phase::Test,
+                    std::process::Command::new("cargo")
+                        .arg("test")
+                        .args(ctx.cmd[1..].iter())
+                        .current_dir(ctx.path),
+                )
+                .map_err(|e| e.in_phase(Phase::Test))?;
             }
             _ => {}
         }
         match &*ctx.cmd[0] {
             "publish" if !ctx.member.private => {
-                out(std::process::Command::new("cargo")
-                    .arg("publish")
-                    .current_dir(&ctx.path))?;
+                let name = &ctx.depmap.cargo(ctx.root, ctx.root_path)?[ctx.xpath];
+                let version = &ctx.member.version;
+                let reg = self
+                    .registry
+                    .or(ctx.root.core.as_ref().and_then(|c| c.cargo.as_ref()));
+                let index = reg.cargo_index();
+                let published = match index {
+                    Some(index) => registry::crate_published(index, name, version)?,
+                    None => None,
+                };
+                if published == Some(true) {
+                    eprintln!("[Publish] {name}@{version} already published");
+                } else {
+                    if let Some(index) = index
+                        && published.is_none()
+                    {
+                        eprintln!(
+                            "[Publish] curl is not installed, not checking {index} for {name}@{version}"
+                        );
+                    }
+                    let _upload = ctx.opts.uploads.lock().unwrap();
+                    out(
+                        ctx.opts,
+                        ctx.xpath,
+                        Phase::Cargo,
+                        std::process::Command::new("cargo")
+                            .arg("publish")
+                            .args(reg.cargo_args())
+                            .current_dir(ctx.path),
+                    )?;
+                    if let Some(index) = index
+                        && published.is_some()
+                        && ctx.has_dependents()
+                    {
+                        registry::wait(ctx.opts, name, version, || {
+                            Ok(registry::crate_published(index, name, version)? == Some(true))
+                        })?;
+                    }
+                }
             }
             "build" => {}
             _ => {}
         }
-        std::fs::write(
-            format!("{}/Cargo.toml", ctx.path),
-            toml::to_string_pretty(&val).map_err(|e| std::io::Error::new(ErrorKind::Other, e))?,
-        )?;
         Ok(())
     }
 }
 
 impl BuildSystem for NPM {
-    fn process(&self, ctx: BuildContext) -> std::io::Result<()> {
-        let mut val: serde_json::Value =
-            serde_json::from_reader(File::open(format!("{}/package.json", ctx.path))?)?;
+    fn process(&self, ctx: BuildContext) -> Result<()> {
+        let mut val = read_json(&format!("{}/package.json", ctx.path))?;
         if ctx.update {
             for (a, b) in [
                 ("version", &ctx.member.version),
@@ -692,111 +1019,155 @@ impl BuildSystem for NPM {
                     o.insert(a.to_owned(), serde_json::Value::String(b.clone()));
                 }
             }
-            if let Some(deps) = val
-                .as_object_mut()
-                .and_then(|o| o.get_mut("dependencies"))
-                .and_then(|d| d.as_object_mut())
-            {
+            let rnpm = ctx.depmap.rnpm(ctx.root, ctx.root_path)?;
+            for (section, range) in self.ranges.sections() {
+                let Some(deps) = val
+                    .as_object_mut()
+                    .and_then(|o| o.get_mut(section))
+                    .and_then(|d| d.as_object_mut())
+                else {
+                    continue;
+                };
                 for (k, v) in deps.iter_mut() {
-                    if let Some(dep_name) = ctx
-                        .depmap
-                        .rnpm(ctx.root, ctx.root_path)?
-                        .get(k)
-                        .and_then(|a| ctx.root.members.get(a))
-                    {
-                        *v = serde_json::Value::String(format!("^{}", &dep_name.version));
+                    if let Some(dep) = rnpm.get(k).and_then(|a| ctx.root.members.get(a)) {
+                        *v = serde_json::Value::String(range.format(&dep.version));
                     }
                 }
             }
         }
+        // Written before building so that the build tools and `npm publish` see the synced
+        // manifest.
+        write(
+            ctx.opts,
+            &format!("{}/package.json", ctx.path),
+            manifest::json(&val, &format!("{}/package.json", ctx.path))?,
+        )?;
         match &*ctx.cmd[0] {
-            "build" | "publish" => match val.get("zshy") {
+            "build" if ctx.fresh => {}
+            "build" | "publish" | "test" => match val.get("zshy") {
                 Some(_) => {
-                    std::fs::write(
-                        format!("{}/package.json", ctx.path),
-                        serde_json::to_vec_pretty(&val)?,
+                    out(
+                        ctx.opts,
+                        ctx.xpath,
+                        Phase::Npm,
+                        std::process::Command::new("npx")
+                            .arg("zshy")
+                            .arg("-p")
+                            .arg(format!("{}/tsconfig.json", ctx.root_path))
+                            .current_dir(ctx.path),
                     )?;
-                    out(std::process::Command::new("npx")
-                        .arg("zshy")
-                        .arg("-p")
-                        .arg(format!("{}/tsconfig.json", ctx.root_path))
-                        .current_dir(&ctx.path))?;
-                    val =
-                        serde_json::from_reader(File::open(format!("{}/package.json", ctx.path))?)?;
+                    // zshy rewrites package.json, e.g. its `exports`.
+                    if !ctx.opts.dry_run {
+                        val = read_json(&format!("{}/package.json", ctx.path))?;
+                    }
                 }
-                None => match val.get("source") {
-                    Some(_) => {
-                        out(std::process::Command::new("npx")
-                            .arg("parcel")
-                            .arg("build")
-                            .arg(format!("./{}", ctx.xpath))
-                            .current_dir(&ctx.root_path))?;
+                None => {
+                    if val.get("source").is_some() {
+                        out(
+                            ctx.opts,
+                            ctx.xpath,
+                            Phase::Npm,
+                            std::process::Command::new("npx")
+                                .arg("parcel")
+                                .arg("build")
+                                .arg(format!("./{}", ctx.xpath))
+                                .current_dir(ctx.root_path),
+                        )?;
                     }
-                    None => {}
-                },
+                }
             },
             _ => {}
         }
         match &*ctx.cmd[0] {
+            "test" if val.pointer("/scripts/test").is_some() => {
+                out(
+                    ctx.opts,
+                    ctx.xpath,
+                    Phase::Test,
+                    std::process::Command::new("npm")
+                        .arg("test")
+                        .arg("--")
+                        .args(ctx.cmd[1..].iter())
+                        .current_dir(ctx.path),
+                )
+                .map_err(|e| e.in_phase(Phase::Test))?;
+            }
             "publish" if !ctx.member.private => {
-                out(std::process::Command::new("npm")
-                    .arg("publish")
-                    .arg("--access")
-                    .arg("public")
-                    .current_dir(&ctx.path))?;
+                let name = &ctx.depmap.npm(ctx.root, ctx.root_path)?[ctx.xpath];
+                let version = &ctx.member.version;
+                let reg = self
+                    .registry
+                    .or(ctx.root.core.as_ref().and_then(|c| c.npm.as_ref()));
+                if registry::npm_published(reg.npm_index(), name, version)? {
+                    eprintln!("[Publish] {name}@{version} already published");
+                } else {
+                    let _upload = ctx.opts.uploads.lock().unwrap();
+                    out(
+                        ctx.opts,
+                        ctx.xpath,
+                        Phase::Npm,
+                        std::process::Command::new("npm")
+                            .arg("publish")
+                            .args(reg.npm_args())
+                            .current_dir(ctx.path),
+                    )?;
+                    if ctx.has_dependents() {
+                        registry::wait(ctx.opts, name, version, || {
+                            registry::npm_published(reg.npm_index(), name, version)
+                        })?;
+                    }
+                }
             }
             "build" => {}
             _ => {}
         }
-        std::fs::write(
-            format!("{}/package.json", ctx.path),
-            serde_json::to_vec_pretty(&val)?,
-        )?;
         Ok(())
     }
 }
 
 impl BuildSystem for Subtree {
-    fn process(&self, ctx: BuildContext) -> std::io::Result<()> {
-        let mut error = OnceCell::new();
+    fn process(&self, ctx: BuildContext) -> Result<()> {
+        let errors = Mutex::new(vec![]);
         std::thread::scope(|s| {
             for (p, v) in self.paths.iter().map(|(p, v)| (p.clone(), v.clone())) {
-                let error = &error;
+                let errors = &errors;
                 let root_path = ctx.root_path;
                 let xpath = ctx.xpath;
+                let opts = ctx.opts;
                 s.spawn(move || {
                     match (move || {
-                        out(std::process::Command::new("git")
-                            .arg("subtree")
-                            .arg("pull")
-                            .arg("-P")
-                            .arg(format!("{root_path}/{xpath}/{p}"))
-                            .arg(v))?;
-                        Ok::<_, std::io::Error>(())
+                        out(
+                            opts,
+                            xpath,
+                            Phase::Subtree,
+                            std::process::Command::new("git")
+                                .arg("subtree")
+                                .arg("pull")
+                                .arg("-P")
+                                .arg(format!("{root_path}/{xpath}/{p}"))
+                                .arg(v),
+                        )?;
+                        Ok::<_, Error>(())
                     })() {
                         Ok(_) => {}
-                        Err(e) => {
-                            let _ = error.set(e);
-                        }
+                        Err(e) => errors.lock().unwrap().push(e),
                     }
                 });
             }
         });
-        if let Some(e) = error.take() {
-            return Err(e);
-        }
-        Ok(())
+        Error::collect(errors.into_inner().unwrap())
     }
 }
 
 impl BuildSystem for Submodule {
-    fn process(&self, ctx: BuildContext) -> std::io::Result<()> {
-        let mut error = OnceCell::new();
+    fn process(&self, ctx: BuildContext) -> Result<()> {
+        let errors = Mutex::new(vec![]);
         std::thread::scope(|s| {
             for (p, v) in self.paths.iter().map(|(p, v)| (p.clone(), v.clone())) {
-                let error = &error;
+                let errors = &errors;
                 let root_path = ctx.root_path;
                 let xpath = ctx.xpath;
+                let opts = ctx.opts;
                 s.spawn(move || {
                     match (move || {
                         let submodule_path = format!("{root_path}/{xpath}/{p}");
@@ -804,36 +1175,41 @@ impl BuildSystem for Submodule {
                         if !std::fs::exists(&submodule_path)?
                             || std::fs::read_dir(&submodule_path)?.next().is_none()
                         {
-                            out(std::process::Command::new("git")
-                                .arg("submodule")
-                                .arg("add")
-                                .arg("-f")
-                                .arg(&v)
-                                .arg(&p)
-                                .current_dir(&format!("{root_path}/{xpath}")))?;
+                            out(
+                                opts,
+                                xpath,
+                                Phase::Submodule,
+                                std::process::Command::new("git")
+                                    .arg("submodule")
+                                    .arg("add")
+                                    .arg("-f")
+                                    .arg(&v)
+                                    .arg(&p)
+                                    .current_dir(format!("{root_path}/{xpath}")),
+                            )?;
                         }
                         // Update/pull the submodule
-                        out(std::process::Command::new("git")
-                            .arg("submodule")
-                            .arg("update")
-                            .arg("--init")
-                            .arg("--recursive")
-                            .arg("--remote")
-                            .arg(&submodule_path))?;
-                        Ok::<_, std::io::Error>(())
+                        out(
+                            opts,
+                            xpath,
+                            Phase::Submodule,
+                            std::process::Command::new("git")
+                                .arg("submodule")
+                                .arg("update")
+                                .arg("--init")
+                                .arg("--recursive")
+                                .arg("--remote")
+                                .arg(&submodule_path),
+                        )?;
+                        Ok::<_, Error>(())
                     })() {
                         Ok(_) => {}
-                        Err(e) => {
-                            let _ = error.set(e);
-                        }
+                        Err(e) => errors.lock().unwrap().push(e),
                     }
                 });
             }
         });
-        if let Some(e) = error.take() {
-            return Err(e);
-        }
-        Ok(())
+        Error::collect(errors.into_inner().unwrap())
     }
 }
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
@@ -0,0 +1,311 @@
+//! Format-preserving edits to member manifests.
+//!
+//! Values are only replaced when they actually change, and replacements keep the
+//! whitespace and comments around the value they replace.
+use serde::Serialize;
+use toml_edit::{Array, DocumentMut, Item, TableLike, Value};
+
+/// Whether `a` and `b` hold the same data, ignoring formatting and quoting style.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same(a, b))
+        }
+        _ => false,
+    }
+}
+
+/// Replace `old` with `v` unless they are the same, keeping the decoration of `old`.
+fn replace(old: &mut Value, mut v: Value) {
+    if !same(old, &v) {
+        *v.decor_mut() = old.decor().clone();
+        *old = v;
+    }
+}
+
+/// Set `table[key]` to `v`, keeping the decoration of the value it replaces.
+pub fn set(table: &mut dyn TableLike, key: &str, v: impl Into<Value>) {
+    match table.get_mut(key) {
+        Some(Item::Value(old)) => replace(old, v.into()),
+        _ => {
+            table.insert(key, Item::Value(v.into()));
+        }
+    }
+}
+
+/// Dependency tables of a Cargo manifest, also found under `target.<cfg>`.
+const DEP_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Set the `version` requirement of every dependency for which `version` returns one, given
+/// the dependency's crate name (its `package` key when renamed).
+///
+/// Covers `[workspace.dependencies]` too. Other keys such as `path` are kept; `workspace = true`
+/// dependencies are left alone.
+pub fn set_dep_versions(doc: &mut DocumentMut, version: impl Fn(&str) -> Option<String>) {
+    fn deps(table: &mut dyn TableLike, version: &dyn Fn(&str) -> Option<String>) {
+        for kind in DEP_TABLES {
+            let Some(t) = table.get_mut(kind).and_then(|t| t.as_table_like_mut()) else {
+                continue;
+            };
+            for (key, item) in t.iter_mut() {
+                match item {
+                    Item::Value(old @ Value::String(_)) => {
+                        if let Some(v) = version(key.get()) {
+                            replace(old, v.into());
+                        }
+                    }
+                    item => {
+                        let inline = item.is_inline_table();
+                        let Some(d) = item.as_table_like_mut() else {
+                            continue;
+                        };
+                        if d.get("workspace").and_then(|w| w.as_bool()) == Some(true) {
+                            continue;
+                        }
+                        let name = d
+                            .get("package")
+                            .and_then(|p| p.as_str())
+                            .unwrap_or(key.get());
+                        if let Some(v) = version(name) {
+                            let added = !d.contains_key("version");
+                            set(d, "version", v);
+                            // An appended key would otherwise land after the last value's padding.
+                            if inline && added {
+                                d.fmt();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    deps(doc.as_table_mut(), &version);
+    if let Some(w) = doc.get_mut("workspace").and_then(|w| w.as_table_like_mut()) {
+        deps(w, &version);
+    }
+    if let Some(targets) = doc.get_mut("target").and_then(|t| t.as_table_like_mut()) {
+        for (_, t) in targets.iter_mut() {
+            if let Some(t) = t.as_table_like_mut() {
+                deps(t, &version);
+            }
+        }
+    }
+}
+
+/// Replace the strings in `array` with `items`, keeping the formatting (and comments) of
+/// elements that stay and indenting new ones like the existing first element, without its
+/// comments.
+pub fn set_strings(array: &mut Array, items: impl IntoIterator<Item = String>) {
+    let new = items.into_iter().map(Value::from).collect::<Vec<_>>();
+    if array.len() == new.len() && array.iter().zip(new.iter()).all(|(a, b)| same(a, b)) {
+        return;
+    }
+    let multiline = array.get(0).is_some_and(|v| {
+        v.decor()
+            .prefix()
+            .and_then(|p| p.as_str())
+            .is_some_and(|p| p.contains('\n'))
+    });
+    if !multiline {
+        array.clear();
+        array.extend(new);
+        array.fmt();
+        return;
+    }
+    let template = array.get(0).unwrap().decor().clone();
+    // Only the first element's indentation, not the comment lines above it.
+    let prefix = template
+        .prefix()
+        .and_then(|p| p.as_str())
+        .and_then(|p| p.rsplit_once('\n'))
+        .map_or_else(|| "\n".to_owned(), |(_, indent)| format!("\n{indent}"));
+    let last_suffix = array
+        .iter()
+        .last()
+        .and_then(|v| v.decor().suffix())
+        .cloned();
+    let mut old = array.iter().cloned().collect::<Vec<_>>();
+    array.clear();
+    let n = new.len();
+    for (i, v) in new.into_iter().enumerate() {
+        let mut v = match old.iter().position(|o| same(o, &v)) {
+            Some(j) => old.remove(j),
+            None => v.decorated(prefix.as_str(), ""),
+        };
+        let suffix = match i + 1 == n {
+            true => last_suffix.clone(),
+            false => template.suffix().cloned(),
+        };
+        v.decor_mut().set_suffix(suffix.unwrap_or_default());
+        array.push_formatted(v);
+    }
+}
+
+/// Serialize `val` in the style of the JSON file currently at `path`: the same indentation
+/// (or none, for single-line files) and the same trailing newline.
+///
+/// Key order is kept by `serde_json`'s `preserve_order` feature.
+pub fn json(val: &serde_json::Value, path: &str) -> std::io::Result<Vec<u8>> {
+    json_like(val, &std::fs::read_to_string(path).unwrap_or_default())
+}
+
+/// Serialize `val` in the style of the JSON text `old`; see [`json`].
+fn json_like(val: &serde_json::Value, old: &str) -> std::io::Result<Vec<u8>> {
+    let indent = match old.trim_end().contains('\n') {
+        true => old
+            .lines()
+            .skip(1)
+            .find(|l| !l.trim().is_empty())
+            .map(|l| &l[..l.len() - l.trim_start().len()]),
+        false if old.is_empty() => Some("  "),
+        false => None,
+    };
+    let mut out = vec![];
+    match indent {
+        Some(indent) => {
+            let f = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            val.serialize(&mut serde_json::Serializer::with_formatter(&mut out, f))?;
+        }
+        _ => serde_json::to_writer(&mut out, val)?,
+    }
+    if old.is_empty() || old.ends_with('\n') {
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(s: &str) -> DocumentMut {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn set_keeps_decor() {
+        let mut d = doc("[package]\nname = \"a\"\nversion = \"0.1.0\" # bumped\n");
+        let p = d["package"].as_table_like_mut().unwrap();
+        set(p, "version", "0.2.0");
+        set(p, "name", "a");
+        set(p, "publish", false);
+        assert_eq!(
+            d.to_string(),
+            "[package]\nname = \"a\"\nversion = \"0.2.0\" # bumped\npublish = false\n"
+        );
+        // Unchanged values keep their quoting.
+        let mut d = doc("[package]\nversion = '0.1.0'\n");
+        set(
+            d["package"].as_table_like_mut().unwrap(),
+            "version",
+            "0.1.0",
+        );
+        assert_eq!(d.to_string(), "[package]\nversion = '0.1.0'\n");
+    }
+
+    fn members(d: &mut DocumentMut, items: &[&str]) {
+        let array = d["workspace"]["members"].as_array_mut().unwrap();
+        set_strings(array, items.iter().map(|s| s.to_string()));
+    }
+
+    #[test]
+    fn set_strings_layouts() {
+        let mut d = doc("[workspace]\nmembers = [\"a\", \"b\"]\n");
+        members(&mut d, &["a", "c"]);
+        assert_eq!(d.to_string(), "[workspace]\nmembers = [\"a\", \"c\"]\n");
+
+        // Comments belong to the element below them.
+        let multiline =
+            "[workspace]\nmembers = [\n    # keep\n    \"a\",\n    # old\n    \"b\",\n]\n";
+        let mut d = doc(multiline);
+        members(&mut d, &["a", "b"]);
+        assert_eq!(d.to_string(), multiline);
+        members(&mut d, &["a", "b", "c"]);
+        assert_eq!(
+            d.to_string(),
+            "[workspace]\nmembers = [\n    # keep\n    \"a\",\n    # old\n    \"b\",\n    \"c\",\n]\n"
+        );
+        members(&mut d, &["a", "c"]);
+        assert_eq!(
+            d.to_string(),
+            "[workspace]\nmembers = [\n    # keep\n    \"a\",\n    \"c\",\n]\n"
+        );
+    }
+
+    #[test]
+    fn dep_versions() {
+        let mut d = doc(r#"[workspace.dependencies]
+a = { version = "0.1.0", path = "a" }
+
+[dependencies]
+a = "0.1.0"
+renamed = { package = "b", version = "0.1", path = "../b" }
+c = { path = "../c" }
+inherited = { workspace = true }
+serde = "1"
+
+[dev-dependencies.a]
+version = "0.1.0" # dev
+path = "../a"
+
+[target.'cfg(unix)'.build-dependencies]
+c = "0.1.0"
+"#);
+        set_dep_versions(&mut d, |name| match name {
+            "a" => Some("0.2.0".to_owned()),
+            "b" => Some("1.0.0".to_owned()),
+            "c" => Some("0.3.0".to_owned()),
+            "inherited" => Some("9.9.9".to_owned()),
+            _ => None,
+        });
+        assert_eq!(
+            d.to_string(),
+            r#"[workspace.dependencies]
+a = { version = "0.2.0", path = "a" }
+
+[dependencies]
+a = "0.2.0"
+renamed = { package = "b", version = "1.0.0", path = "../b" }
+c = { path = "../c", version = "0.3.0" }
+inherited = { workspace = true }
+serde = "1"
+
+[dev-dependencies.a]
+version = "0.2.0" # dev
+path = "../a"
+
+[target.'cfg(unix)'.build-dependencies]
+c = "0.3.0"
+"#
+        );
+    }
+
+    #[test]
+    fn json_styles() {
+        let val = serde_json::json!({"name": "a", "deps": {"b": "^1.0.0"}});
+        let styled = |old: &str| String::from_utf8(json_like(&val, old).unwrap()).unwrap();
+        assert_eq!(
+            styled(""),
+            "{\n  \"name\": \"a\",\n  \"deps\": {\n    \"b\": \"^1.0.0\"\n  }\n}\n"
+        );
+        assert_eq!(
+            styled("{\n\t\"name\": \"x\"\n}\n"),
+            "{\n\t\"name\": \"a\",\n\t\"deps\": {\n\t\t\"b\": \"^1.0.0\"\n\t}\n}\n"
+        );
+        assert_eq!(
+            styled("{\n    \"name\": \"x\"\n}"),
+            "{\n    \"name\": \"a\",\n    \"deps\": {\n        \"b\": \"^1.0.0\"\n    }\n}"
+        );
+        assert_eq!(
+            styled("{\"name\":\"x\"}\n"),
+            "{\"name\":\"a\",\"deps\":{\"b\":\"^1.0.0\"}}\n"
+        );
+        assert_eq!(
+            styled("{\"name\":\"x\"}"),
+            "{\"name\":\"a\",\"deps\":{\"b\":\"^1.0.0\"}}"
+        );
+    }
+}
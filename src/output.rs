@@ -0,0 +1,84 @@
+use crate::error::Phase;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write, stderr, stdout},
+    sync::Mutex,
+};
+
+/// How child process output is shown.
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum Mode {
+    /// Print each line as soon as it arrives
+    #[default]
+    Stream,
+    /// Hold each member's lines until the member finishes, then print them together
+    Grouped,
+}
+
+/// A prefixed line of child output and whether it goes to stderr.
+type Line = (bool, Vec<u8>);
+
+/// Prefixes child output with `[member phase]` and routes it according to [`Mode`].
+#[derive(Default)]
+pub struct Output {
+    mode: Mode,
+    /// Buffered lines per member, in grouped mode.
+    groups: Mutex<BTreeMap<String, Vec<Line>>>,
+}
+
+impl Output {
+    pub fn new(mode: Mode) -> Self {
+        Output {
+            mode,
+            groups: Mutex::default(),
+        }
+    }
+
+    fn emit(is_stderr: bool, line: &[u8]) {
+        let _ = if is_stderr {
+            stderr().lock().write_all(line)
+        } else {
+            stdout().lock().write_all(line)
+        };
+    }
+
+    /// Forward `r` line by line until EOF, returning everything read.
+    pub fn pump(&self, r: impl Read, xpath: &str, phase: Phase, is_stderr: bool) -> String {
+        let mut r = BufReader::new(r);
+        let mut all = vec![];
+        let mut buf = vec![];
+        while let Ok(n @ 1..) = r.read_until(b'\n', &mut buf) {
+            all.extend_from_slice(&buf[..n]);
+            if !buf.ends_with(b"\n") {
+                buf.push(b'\n');
+            }
+            let mut line = format!("[{xpath} {phase}] ").into_bytes();
+            line.append(&mut buf);
+            match self.mode {
+                Mode::Stream => Self::emit(is_stderr, &line),
+                Mode::Grouped => self
+                    .groups
+                    .lock()
+                    .unwrap()
+                    .entry(xpath.to_owned())
+                    .or_default()
+                    .push((is_stderr, line)),
+            }
+        }
+        String::from_utf8_lossy(&all).into_owned()
+    }
+
+    /// Print everything buffered for `xpath`. Does nothing in stream mode.
+    pub fn flush(&self, xpath: &str) {
+        let lines = self.groups.lock().unwrap().remove(xpath);
+        // Hold both locks so concurrently finishing members don't interleave.
+        let (mut o, mut e) = (stdout().lock(), stderr().lock());
+        for (is_stderr, line) in lines.into_iter().flatten() {
+            let _ = if is_stderr {
+                e.write_all(&line)
+            } else {
+                o.write_all(&line)
+            };
+        }
+    }
+}
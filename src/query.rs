@@ -0,0 +1,121 @@
+//! `pupi query`: list members for shell scripts.
+use crate::{DepMap, Root, cli::QueryArgs, error::Result, graph, select};
+use std::collections::BTreeSet;
+
+/// The members matching every filter in `args`, formatted with `args.format`, one per line.
+pub fn query(root: &Root, args: &QueryArgs) -> Result<Vec<String>> {
+    let root_path = &args.root_path;
+    let mut members = root
+        .members
+        .iter()
+        .filter(|(_, m)| !args.cargo || m.cargo.is_some())
+        .filter(|(_, m)| !args.npm || m.npm.is_some())
+        .filter(|(_, m)| !args.public || !m.private)
+        .map(|(x, _)| x.clone())
+        .collect::<BTreeSet<_>>();
+    if let Some(p) = args.depends_on.as_ref() {
+        let deps = select::matching(root, std::slice::from_ref(p))?;
+        members.retain(|x| root.members[x].deps.keys().any(|d| deps.contains(d)));
+    }
+    if let Some(p) = args.dependents_of.as_ref() {
+        let seeds = select::matching(root, std::slice::from_ref(p))?;
+        let dependents = graph::with_dependents(root, seeds.iter().cloned());
+        members.retain(|x| dependents.contains(x) && !seeds.contains(x));
+    }
+    if let Some(since) = args.changed_since.as_deref() {
+        let changed = select::changed(root, root_path, since)?;
+        members.retain(|x| changed.contains(x));
+    }
+    let d = DepMap::default();
+    let mut lines = vec![];
+    for xpath in members {
+        let mut line = args.format.replace("{path}", &xpath);
+        if line.contains("{npm}") {
+            let name = d.npm(root, root_path)?.get(&xpath);
+            line = line.replace("{npm}", name.map_or("", |n| n));
+        }
+        if line.contains("{crate}") {
+            let name = d.cargo(root, root_path)?.get(&xpath);
+            line = line.replace("{crate}", name.map_or("", |n| n));
+        }
+        lines.push(line.replace("{version}", &root.members[&xpath].version));
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn filters_and_format() {
+        // `w` depends on `a`, `t` on `w`.
+        let dir = TempDir::new("query");
+        dir.write("a/Cargo.toml", "[package]\nname = \"crate-a\"\n");
+        dir.write("w/package.json", r#"{"name": "@s/w"}"#);
+        let root: Root = serde_json::from_str(
+            r#"{
+                "a": {"deps": {}, "version": "1.0.0", "description": "", "cargo": {}},
+                "t": {"deps": {"w": {}}, "version": "3.0.0", "description": ""},
+                "w": {"deps": {"a": {}}, "version": "2.0.0", "description": "",
+                      "private": true, "npm": {}}
+            }"#,
+        )
+        .unwrap();
+        let query = |args: QueryArgs| {
+            let args = QueryArgs {
+                root_path: dir.path().to_owned(),
+                ..args
+            };
+            query(&root, &args)
+        };
+        let paths = |args: QueryArgs| {
+            query(QueryArgs {
+                format: "{path}".to_owned(),
+                ..args
+            })
+            .unwrap()
+        };
+        assert_eq!(paths(QueryArgs::default()), ["a", "t", "w"]);
+        let cargo = QueryArgs {
+            cargo: true,
+            ..Default::default()
+        };
+        assert_eq!(paths(cargo), ["a"]);
+        let npm = QueryArgs {
+            npm: true,
+            ..Default::default()
+        };
+        assert_eq!(paths(npm), ["w"]);
+        let public = QueryArgs {
+            public: true,
+            ..Default::default()
+        };
+        assert_eq!(paths(public), ["a", "t"]);
+        let depends_on = QueryArgs {
+            depends_on: Some("a".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(paths(depends_on), ["w"]);
+        let dependents_of = QueryArgs {
+            dependents_of: Some("a".to_owned()),
+            public: true,
+            ..Default::default()
+        };
+        assert_eq!(paths(dependents_of), ["t"]);
+        let unknown = QueryArgs {
+            depends_on: Some("nope".to_owned()),
+            ..Default::default()
+        };
+        assert!(query(unknown).is_err());
+        let format = QueryArgs {
+            format: "{path}: {crate}{npm}@{version}".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(format).unwrap(),
+            ["a: crate-a@1.0.0", "t: @3.0.0", "w: @s/w@2.0.0"]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Registry lookups, so that `pupi publish` can skip versions that were already uploaded.
+use crate::{
+    Options,
+    error::{ErrorKind, Result},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    process::{Command, Output},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// The sparse index of crates.io, used when no Cargo registry is configured.
+pub const CRATES_IO_INDEX: &str = "https://index.crates.io";
+
+fn is_url(index: &str) -> bool {
+    index.starts_with("http://") || index.starts_with("https://")
+}
+
+/// Where and how a member is published. Set on a member's `cargo`/`npm` config, with defaults
+/// for every member in the `//` section's `cargo`/`npm`.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone)]
+#[non_exhaustive]
+pub struct Registry {
+    /// Cargo: a registry name from `.cargo/config.toml` or an index URL. npm: a registry URL.
+    /// [default: crates.io / the npm registry]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// Checked for already-published versions. Cargo: a sparse index URL or a local index
+    /// directory. npm: a registry URL or a local packument directory. [default: `registry`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    /// npm `--access` (`public` or `restricted`) [default: `public`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>,
+    /// npm dist-tag (`--tag`) [default: `latest`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Extra arguments for `cargo publish`/`npm publish`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+}
+
+impl Registry {
+    /// These settings, with the unset ones taken from `defaults`.
+    pub fn or(&self, defaults: Option<&Registry>) -> Registry {
+        let Some(d) = defaults else {
+            return self.clone();
+        };
+        Registry {
+            registry: self.registry.clone().or_else(|| d.registry.clone()),
+            index: self.index.clone().or_else(|| d.index.clone()),
+            access: self.access.clone().or_else(|| d.access.clone()),
+            tag: self.tag.clone().or_else(|| d.tag.clone()),
+            flags: self.flags.clone().or_else(|| d.flags.clone()),
+        }
+    }
+
+    /// Arguments for `cargo publish`.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        match self.registry.as_deref() {
+            Some(r) if r.contains("://") => args.extend(["--index".to_owned(), r.to_owned()]),
+            Some(r) => args.extend(["--registry".to_owned(), r.to_owned()]),
+            None => {}
+        }
+        args.extend(self.flags.iter().flatten().cloned());
+        args
+    }
+
+    /// The Cargo index to check for published versions, if one is known: a registry given by
+    /// name has no index unless `index` is set.
+    pub fn cargo_index(&self) -> Option<&str> {
+        match (self.index.as_deref(), self.registry.as_deref()) {
+            (Some(i), _) => Some(i),
+            (None, Some(r)) if r.contains("://") => Some(r),
+            (None, Some(_)) => None,
+            (None, None) => Some(CRATES_IO_INDEX),
+        }
+    }
+
+    /// Arguments for `npm publish`.
+    pub fn npm_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--access".to_owned(),
+            self.access.clone().unwrap_or_else(|| "public".to_owned()),
+        ];
+        if let Some(r) = self.registry.as_ref() {
+            args.extend(["--registry".to_owned(), r.clone()]);
+        }
+        if let Some(t) = self.tag.as_ref() {
+            args.extend(["--tag".to_owned(), t.clone()]);
+        }
+        args.extend(self.flags.iter().flatten().cloned());
+        args
+    }
+
+    /// The npm registry URL or packument directory to check for published versions.
+    pub fn npm_index(&self) -> Option<&str> {
+        self.index.as_deref().or(self.registry.as_deref())
+    }
+}
+
+fn failed(c: &Command, o: Output) -> crate::error::Error {
+    ErrorKind::Command {
+        command: crate::describe(c),
+        status: o.status,
+        stdout: String::from_utf8_lossy(&o.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&o.stderr).into_owned(),
+    }
+    .into()
+}
+
+/// The path of a crate's file in a Cargo index.
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Whether crate `name` at `version` is in the Cargo registry `index`: a sparse index URL, or a
+/// directory laid out like one (for offline tests). Index URLs are fetched with `curl`; `None`
+/// if it is not installed.
+pub fn crate_published(index: &str, name: &str, version: &str) -> Result<Option<bool>> {
+    let index = index.trim_start_matches("sparse+").trim_end_matches('/');
+    let path = index_path(name);
+    let lines = if is_url(index) {
+        let mut c = Command::new("curl");
+        c.arg("-sSL")
+            .arg("--connect-timeout")
+            .arg("10")
+            .arg("--max-time")
+            .arg("30")
+            .arg("--write-out")
+            .arg("\n%{http_code}")
+            .arg(format!("{index}/{path}"));
+        let o = match c.output() {
+            Ok(o) => o,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let stdout = String::from_utf8_lossy(&o.stdout).into_owned();
+        let (body, code) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+        match code {
+            "200" => body.to_owned(),
+            "404" | "410" | "451" => return Ok(Some(false)),
+            _ => return Err(failed(&c, o)),
+        }
+    } else {
+        match std::fs::read_to_string(format!("{index}/{path}")) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Some(false)),
+            Err(e) => return Err(e.into()),
+        }
+    };
+    Ok(Some(lines.lines().any(|l| {
+        serde_json::from_str::<serde_json::Value>(l)
+            .is_ok_and(|v| v.get("vers").and_then(|v| v.as_str()) == Some(version))
+    })))
+}
+
+/// Whether npm package `name` at `version` exists, according to `npm view` against the default
+/// registry or `index` if it is a URL, or to the packument `<index>/<name>.json` if `index` is a
+/// directory (for offline tests).
+pub fn npm_published(index: Option<&str>, name: &str, version: &str) -> Result<bool> {
+    if let Some(dir) = index.filter(|i| !is_url(i)) {
+        let packument = match std::fs::read_to_string(format!("{dir}/{name}.json")) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let val: serde_json::Value =
+            serde_json::from_str(&packument).map_err(|e| ErrorKind::Manifest {
+                path: format!("{dir}/{name}.json"),
+                message: e.to_string(),
+            })?;
+        return Ok(val
+            .get("versions")
+            .and_then(|v| v.as_object())
+            .is_some_and(|v| v.contains_key(version)));
+    }
+    let mut c = Command::new("npm");
+    c.arg("view")
+        .arg(format!("{name}@{version}"))
+        .arg("version");
+    if let Some(url) = index {
+        c.arg("--registry").arg(url);
+    }
+    let o = c.output()?;
+    if o.status.success() {
+        return Ok(!o.stdout.trim_ascii().is_empty());
+    }
+    // An unknown package is a 404 rather than an empty result.
+    if String::from_utf8_lossy(&o.stderr).contains("E404") {
+        return Ok(false);
+    }
+    Err(failed(&c, o))
+}
+
+/// Poll `published` until `name@version` resolves, for up to `--wait` seconds.
+pub fn wait(
+    opts: &Options,
+    name: &str,
+    version: &str,
+    published: impl Fn() -> Result<bool>,
+) -> Result<()> {
+    let secs = opts.publish.wait;
+    if opts.dry_run || secs == 0 {
+        return Ok(());
+    }
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    while !published()? {
+        if Instant::now() >= deadline {
+            return Err(ErrorKind::Unresolved {
+                name: name.to_owned(),
+                version: version.to_owned(),
+                secs,
+            }
+            .into());
+        }
+        sleep(Duration::from_secs(opts.publish.poll.max(1)));
+    }
+    eprintln!("[Publish] {name}@{version} is available");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn index_paths() {
+        assert_eq!(index_path("a"), "1/a");
+        assert_eq!(index_path("ab"), "2/ab");
+        assert_eq!(index_path("abc"), "3/a/abc");
+        assert_eq!(index_path("serde"), "se/rd/serde");
+        assert_eq!(index_path("Serde_JSON"), "se/rd/serde_json");
+    }
+
+    #[test]
+    fn crate_in_local_index() {
+        let dir = TempDir::new("cargo-index");
+        dir.write(
+            "se/rd/serde",
+            "{\"name\":\"serde\",\"vers\":\"1.0.0\"}\n{\"name\":\"serde\",\"vers\":\"1.0.1\"}\n",
+        );
+        let index = dir.path();
+        assert_eq!(
+            crate_published(index, "serde", "1.0.1").unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            crate_published(index, "serde", "1.0.2").unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            crate_published(index, "other", "1.0.0").unwrap(),
+            Some(false)
+        );
+        let trailing = format!("{index}/");
+        assert_eq!(
+            crate_published(&trailing, "serde", "1.0.0").unwrap(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn npm_in_local_packuments() {
+        let dir = TempDir::new("npm-packuments");
+        dir.write(
+            "@x/core.json",
+            "{\"name\":\"@x/core\",\"versions\":{\"1.0.0\":{}}}",
+        );
+        dir.write("broken.json", "{");
+        let index = Some(dir.path());
+        assert!(npm_published(index, "@x/core", "1.0.0").unwrap());
+        assert!(!npm_published(index, "@x/core", "1.1.0").unwrap());
+        assert!(!npm_published(index, "@x/other", "1.0.0").unwrap());
+        assert!(npm_published(index, "broken", "1.0.0").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{
+    DepMap, Options, Root,
+    error::{ErrorKind, Phase, Result},
+    git, out,
+};
+use std::process::Command;
+
+/// Fail unless the working tree at `root_path` has no uncommitted changes to tracked files.
+/// Untracked files, such as the build cache in `.pupi`, are never part of the release commit.
+pub fn ensure_clean(root_path: &str) -> Result<()> {
+    if !git::query(
+        root_path,
+        &["status", "--porcelain", "--untracked-files=no"],
+    )?
+    .trim()
+    .is_empty()
+    {
+        return Err(ErrorKind::Dirty {
+            path: root_path.to_owned(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Commit the manifest rewrites of a publish run and create an annotated tag for every member
+/// in `published`, named after `RootCore::tag_format`. Tags that already exist, e.g. from an
+/// earlier run that published the same versions, are left alone.
+pub fn release(
+    opts: &Options,
+    root: &Root,
+    root_path: &str,
+    depmap: &DepMap,
+    published: &[String],
+) -> Result<()> {
+    if published.is_empty() {
+        return Ok(());
+    }
+    let namer = git::Tags::new(root, root_path, depmap);
+    let mut tags = vec![];
+    for x in published {
+        let tag = namer.tag(x, &root.members[x].version)?;
+        if git::query(root_path, &["tag", "--list", &tag])?
+            .trim()
+            .is_empty()
+        {
+            tags.push(tag);
+        } else {
+            eprintln!("[Release] {tag} already exists");
+        }
+    }
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let changed = !git::query(
+        root_path,
+        &["status", "--porcelain", "--untracked-files=no"],
+    )?
+    .trim()
+    .is_empty();
+    if changed || opts.dry_run {
+        out(
+            opts,
+            root_path,
+            Phase::Release,
+            Command::new("git")
+                .arg("commit")
+                .arg("-a")
+                .arg("-m")
+                .arg(format!("Release {}", tags.join(", ")))
+                .current_dir(root_path),
+        )?;
+    }
+    for tag in tags.iter() {
+        out(
+            opts,
+            root_path,
+            Phase::Release,
+            Command::new("git")
+                .arg("tag")
+                .arg("-a")
+                .arg(tag)
+                .arg("-m")
+                .arg(format!("Release {tag}"))
+                .current_dir(root_path),
+        )?;
+        if opts.dry_run {
+            eprintln!("[Release] Would tag {tag}");
+        } else {
+            eprintln!("[Release] Tagged {tag}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::error::{Error, ErrorKind, Phase, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// What happened to a single member during a run.
+pub enum Outcome {
+    Succeeded,
+    Failed(Vec<Error>),
+    /// Not attempted because a dependency did not succeed.
+    Skipped(String),
+    /// Not attempted because another member failed and `--keep-going` was not given.
+    Cancelled,
+    /// Not selected for this run; never shown in the summary.
+    Unselected,
+}
+
+/// Per-member outcomes of a run, shared by every worker.
+pub struct Report {
+    keep_going: bool,
+    cancelled: AtomicBool,
+    outcomes: Mutex<BTreeMap<String, Outcome>>,
+}
+
+impl Report {
+    pub fn new(keep_going: bool) -> Self {
+        Report {
+            keep_going,
+            cancelled: AtomicBool::new(false),
+            outcomes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Whether new work should be started. Work already in flight is allowed to finish.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn record(&self, member: &str, outcome: Outcome) {
+        if matches!(outcome, Outcome::Failed(_)) && !self.keep_going {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+        self.outcomes
+            .lock()
+            .unwrap()
+            .insert(member.to_owned(), outcome);
+    }
+
+    /// The first of `deps` that did not succeed, if any. A member that built but failed its
+    /// tests still counts as succeeded here, so that its dependents are tested too.
+    pub fn failed_dep<'a>(&self, deps: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        let outcomes = self.outcomes.lock().unwrap();
+        deps.into_iter().find(|d| match outcomes.get(*d) {
+            Some(Outcome::Failed(e)) => !e.iter().all(|e| matches!(e.phase, Some(Phase::Test))),
+            Some(Outcome::Skipped(_) | Outcome::Cancelled) => true,
+            _ => false,
+        })
+    }
+
+    /// Members that succeeded so far.
+    pub fn succeeded(&self) -> BTreeSet<String> {
+        let outcomes = self.outcomes.lock().unwrap();
+        outcomes
+            .iter()
+            .filter(|(_, o)| matches!(o, Outcome::Succeeded))
+            .map(|(m, _)| m.clone())
+            .collect()
+    }
+
+    /// Print the summary table and every error, then fail if any member did.
+    pub fn finish(self, selected: &BTreeSet<String>) -> Result<()> {
+        let outcomes = self.outcomes.into_inner().unwrap();
+        // Unselected members only show up when they failed, e.g. on an unknown `deps` key.
+        let rows = selected
+            .iter()
+            .chain(
+                outcomes
+                    .iter()
+                    .filter(|(_, o)| matches!(o, Outcome::Failed(_)))
+                    .map(|(m, _)| m),
+            )
+            .collect::<BTreeSet<_>>();
+        let width = rows.iter().map(|m| m.len()).max().unwrap_or(0);
+        let mut failed = 0;
+        let mut errors = vec![];
+        eprintln!("[Summary]");
+        for m in rows {
+            let (status, detail) = match outcomes.get(m) {
+                Some(Outcome::Succeeded) => ("succeeded", String::new()),
+                Some(Outcome::Failed(e)) => {
+                    failed += 1;
+                    errors.extend(e);
+                    ("failed", format!("{} error(s)", e.len()))
+                }
+                Some(Outcome::Skipped(why)) => ("skipped", why.clone()),
+                Some(Outcome::Cancelled | Outcome::Unselected) | None => {
+                    ("cancelled", String::new())
+                }
+            };
+            eprintln!("  {m:width$}  {status:9}  {detail}");
+        }
+        for e in errors {
+            eprintln!("error: {}", e.render());
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(ErrorKind::Failed(n).into()),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Root, graph};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+/// A counting semaphore capping how many heavyweight tools (`cargo`, `npm`, `git`, ...) run at once.
+pub struct Limit {
+    max: usize,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+pub struct LimitGuard<'a> {
+    limit: &'a Limit,
+}
+
+impl Limit {
+    pub fn new(max: usize) -> Self {
+        Limit {
+            max: max.max(1),
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> LimitGuard<'_> {
+        let mut used = self
+            .freed
+            .wait_while(self.used.lock().unwrap(), |u| *u >= self.max)
+            .unwrap();
+        *used += 1;
+        LimitGuard { limit: self }
+    }
+}
+
+impl Drop for LimitGuard<'_> {
+    fn drop(&mut self) {
+        *self.limit.used.lock().unwrap() -= 1;
+        self.limit.freed.notify_one();
+    }
+}
+
+/// The default worker count: one per available CPU.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+struct Queue<'a> {
+    ready: VecDeque<&'a str>,
+    /// Number of unfinished dependencies of each member that is not ready yet.
+    waiting: BTreeMap<&'a str, usize>,
+    running: usize,
+}
+
+/// Wait for a ready member, or return `None` once nothing is ready or running.
+fn next<'a>(changed: &Condvar, q: MutexGuard<'_, Queue<'a>>) -> Option<&'a str> {
+    let mut q = changed
+        .wait_while(q, |q| q.ready.is_empty() && q.running > 0)
+        .unwrap();
+    let x = q.ready.pop_front()?;
+    q.running += 1;
+    Some(x)
+}
+
+/// Run `task` for every member of `root` on `jobs` worker threads, starting a member only
+/// once every member it depends on has finished, including the `pkg_name` of its
+/// `Dep::subrepo` deps (see `graph::local_deps`).
+///
+/// `root` must be free of cycles and unknown `deps` keys; see `check::validate`.
+pub fn run(root: &Root, jobs: usize, task: impl Fn(&str) + Sync) {
+    let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut queue = Queue {
+        ready: VecDeque::new(),
+        waiting: BTreeMap::new(),
+        running: 0,
+    };
+    for (xpath, member) in root.members.iter() {
+        let deps = graph::local_deps(member)
+            .into_iter()
+            .filter(|d| root.members.contains_key(*d))
+            .collect::<Vec<_>>();
+        for d in deps.iter() {
+            dependents.entry(d).or_default().push(xpath);
+        }
+        match deps.len() {
+            0 => queue.ready.push_back(xpath),
+            n => {
+                queue.waiting.insert(xpath, n);
+            }
+        }
+    }
+    let queue = Mutex::new(queue);
+    let changed = Condvar::new();
+    std::thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| {
+                let mut q = queue.lock().unwrap();
+                while let Some(x) = next(&changed, q) {
+                    task(x);
+                    q = queue.lock().unwrap();
+                    q.running -= 1;
+                    for d in dependents.get(x).into_iter().flatten() {
+                        let n = q.waiting.get_mut(d).unwrap();
+                        *n -= 1;
+                        if *n == 0 {
+                            q.waiting.remove(d);
+                            q.ready.push_back(d);
+                        }
+                    }
+                    changed.notify_all();
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_subrepo_pkg() {
+        // `q` depends on a member of `p`'s subtree, so it must wait for `p`.
+        let root: Root = serde_json::from_str(
+            r#"{
+                "p": {"deps": {}, "version": "1.0.0", "description": ""},
+                "q": {"deps": {"x": {"subrepo": {"pkg_name": "p", "pkg": {},
+                      "subrepo": "vendor", "nest": {}}}}, "version": "1.0.0", "description": ""},
+                "r": {"deps": {"q": {}}, "version": "1.0.0", "description": ""}
+            }"#,
+        )
+        .unwrap();
+        let log = Mutex::new(vec![]);
+        run(&root, 4, |x| {
+            log.lock().unwrap().push(format!("start {x}"));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            log.lock().unwrap().push(format!("end {x}"));
+        });
+        let log = log.into_inner().unwrap();
+        let at = |e: &str| log.iter().position(|l| l == e).unwrap();
+        assert!(at("end p") < at("start q"), "{log:?}");
+        assert!(at("end q") < at("start r"), "{log:?}");
+    }
+}
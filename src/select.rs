@@ -0,0 +1,243 @@
+use crate::{
+    Root,
+    cli::SelectArgs,
+    error::{ErrorKind, Result},
+    git, graph,
+};
+use std::collections::BTreeSet;
+
+fn normalize(mut a: &str) -> &str {
+    while let Some(b) = a.strip_prefix("./") {
+        a = b;
+    }
+    a
+}
+
+/// Resolve `patterns` (exact member paths or globs) to member paths in `root`.
+/// A pattern that matches nothing is an error, so typos don't silently select nothing.
+pub fn matching(root: &Root, patterns: &[String]) -> Result<BTreeSet<String>> {
+    let mut m = BTreeSet::new();
+    for p in patterns {
+        let pat = glob::Pattern::new(normalize(p))
+            .map_err(|e| ErrorKind::Selection(format!("{p}: {e}")))?;
+        let found = root
+            .members
+            .keys()
+            .filter(|k| normalize(k) == normalize(p) || pat.matches(normalize(k)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            return Err(ErrorKind::Selection(format!("no member matches `{p}`")).into());
+        }
+        m.extend(found);
+    }
+    Ok(m)
+}
+
+/// Whether `file` is `dir` or inside it. The empty path contains everything.
+fn contains(dir: &str, file: &str) -> bool {
+    dir.is_empty()
+        || file
+            .strip_prefix(dir)
+            .is_some_and(|r| r.is_empty() || r.starts_with('/'))
+}
+
+/// The directories owned by each member, relative to the root and longest first: each
+/// member's own path plus its subtree and submodule paths.
+fn owners(root: &Root) -> Vec<(String, &String)> {
+    let mut owners = vec![];
+    for (xpath, m) in root.members.iter() {
+        let base = match normalize(xpath).trim_end_matches('/') {
+            "." => "",
+            b => b,
+        };
+        owners.push((base.to_owned(), xpath));
+        let nested = m.subtree.iter().flat_map(|s| s.paths.keys());
+        for p in nested.chain(m.submodule.iter().flat_map(|s| s.paths.keys())) {
+            let p = normalize(p).trim_end_matches('/');
+            owners.push((
+                format!("{base}/{p}").trim_start_matches('/').to_owned(),
+                xpath,
+            ));
+        }
+    }
+    // Longest first, so the first container of a file is its owner.
+    owners.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.len()));
+    owners
+}
+
+/// The member owning `file`, given the `owners` of a root.
+fn owner<'a>(owners: &[(String, &'a String)], file: &str) -> Option<&'a String> {
+    owners
+        .iter()
+        .find(|(dir, _)| contains(dir, file))
+        .map(|(_, xpath)| *xpath)
+}
+
+/// Members with files changed between `since` and the working tree, per `git diff`. Each file
+/// belongs to the member with the longest path containing it, counting each member's subtree and
+/// submodule paths as paths of that member.
+pub fn changed(root: &Root, root_path: &str, since: &str) -> Result<BTreeSet<String>> {
+    let owners = owners(root);
+    let diff = git::query(
+        root_path,
+        &["diff", "--name-only", "--relative", since, "--"],
+    )?;
+    Ok(diff
+        .lines()
+        .filter_map(|f| owner(&owners, f))
+        .cloned()
+        .collect())
+}
+
+/// Compute the set of members a run should process.
+///
+/// Starts from `--only`/`--with-deps`/`--with-dependents`/`--since` (or every member when none
+/// is given) and then removes `--exclude`d members.
+pub fn select(root: &Root, root_path: &str, args: &SelectArgs) -> Result<BTreeSet<String>> {
+    let mut selected = if args.only.is_empty()
+        && args.with_deps.is_empty()
+        && args.with_dependents.is_empty()
+        && args.since.is_none()
+    {
+        root.members.keys().cloned().collect()
+    } else {
+        let mut s = matching(root, &args.only)?;
+        s.extend(graph::with_deps(root, matching(root, &args.with_deps)?));
+        s.extend(graph::with_dependents(
+            root,
+            matching(root, &args.with_dependents)?,
+        ));
+        if let Some(since) = args.since.as_deref() {
+            s.extend(graph::with_dependents(
+                root,
+                changed(root, root_path, since)?,
+            ));
+        }
+        s
+    };
+    for x in matching(root, &args.exclude)? {
+        selected.remove(&x);
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Subtree, test_util::members};
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn pats(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn matching_patterns() {
+        let root = members(&[("pkg/a", &[]), ("pkg/b", &[]), ("web", &[])]);
+        assert_eq!(
+            matching(&root, &pats(&["pkg/*"])).unwrap(),
+            set(&["pkg/a", "pkg/b"])
+        );
+        assert_eq!(matching(&root, &pats(&["./web"])).unwrap(), set(&["web"]));
+        assert_eq!(
+            matching(&root, &pats(&["web", "pkg/a"])).unwrap(),
+            set(&["pkg/a", "web"])
+        );
+        assert_eq!(matching(&root, &[]).unwrap(), set(&[]));
+        // Unmatched names and globs, and invalid globs, are errors.
+        for bad in ["nope", "pkg/c*", "["] {
+            assert!(matching(&root, &pats(&[bad])).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn contains_paths() {
+        assert!(contains("pkg", "pkg"));
+        assert!(contains("pkg", "pkg/src/lib.rs"));
+        assert!(!contains("pkg", "pkg-extra/src/lib.rs"));
+        assert!(!contains("pkg/a", "pkg"));
+        assert!(contains("", "README.md"));
+    }
+
+    #[test]
+    fn changed_files_owners() {
+        let mut root = members(&[
+            (".", &[]),
+            ("./pkg/", &[]),
+            ("pkg-extra", &[]),
+            ("pkg/nested", &[]),
+            ("vendor", &[]),
+        ]);
+        // The root's subtree is a longer path than the `vendor` member containing it.
+        root.members.get_mut(".").unwrap().subtree = Some(Subtree {
+            paths: [("./vendor/lib/".to_owned(), "url".to_owned())].into(),
+        });
+        let owners = owners(&root);
+        let owner = |f| owner(&owners, f).map(|x| x.as_str());
+        assert_eq!(owner("pkg/src/lib.rs"), Some("./pkg/"));
+        assert_eq!(owner("pkg-extra/src/lib.rs"), Some("pkg-extra"));
+        assert_eq!(owner("pkg/nested/Cargo.toml"), Some("pkg/nested"));
+        assert_eq!(owner("pkg/nestedx"), Some("./pkg/"));
+        assert_eq!(owner("vendor/lib/src/a.rs"), Some("."));
+        assert_eq!(owner("vendor/README.md"), Some("vendor"));
+        assert_eq!(owner("Cargo.lock"), Some("."));
+        // Without a root member, files outside every member belong to none.
+        root.members.remove(".");
+        let owners = super::owners(&root);
+        assert_eq!(super::owner(&owners, "Cargo.lock"), None);
+    }
+
+    #[test]
+    fn select_combines_flags() {
+        // core <- lib <- app, plus an unrelated tool
+        let root = members(&[
+            ("app", &["lib"]),
+            ("core", &[]),
+            ("lib", &["core"]),
+            ("tool", &[]),
+        ]);
+        let select = |args: SelectArgs| select(&root, ".", &args).unwrap();
+        assert_eq!(
+            select(SelectArgs::default()),
+            set(&["app", "core", "lib", "tool"])
+        );
+        assert_eq!(
+            select(SelectArgs {
+                only: pats(&["tool"]),
+                ..Default::default()
+            }),
+            set(&["tool"])
+        );
+        assert_eq!(
+            select(SelectArgs {
+                with_deps: pats(&["app"]),
+                ..Default::default()
+            }),
+            set(&["app", "core", "lib"])
+        );
+        assert_eq!(
+            select(SelectArgs {
+                with_dependents: pats(&["lib"]),
+                exclude: pats(&["app"]),
+                ..Default::default()
+            }),
+            set(&["lib"])
+        );
+        assert_eq!(
+            select(SelectArgs {
+                exclude: pats(&["t*"]),
+                ..Default::default()
+            }),
+            set(&["app", "core", "lib"])
+        );
+        let args = SelectArgs {
+            exclude: pats(&["missing"]),
+            ..Default::default()
+        };
+        assert!(super::select(&root, ".", &args).is_err());
+    }
+}
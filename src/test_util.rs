@@ -0,0 +1,48 @@
+//! Helpers shared by the unit tests.
+use crate::Root;
+use std::{fs, path::PathBuf};
+
+/// A root with `members`, each given as its xpath and the xpaths it depends on.
+pub fn members(members: &[(&str, &[&str])]) -> Root {
+    let members = members
+        .iter()
+        .map(|(xpath, deps)| {
+            let deps = deps.iter().map(|d| (*d, serde_json::json!({})));
+            let member = serde_json::json!({
+                "deps": serde_json::Map::from_iter(deps.map(|(d, v)| (d.to_owned(), v))),
+                "version": "1.0.0",
+                "description": "",
+            });
+            (xpath.to_string(), member)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::from_value(members.into()).unwrap()
+}
+
+/// A fresh directory under the system temp dir, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("pupi-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
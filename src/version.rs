@@ -0,0 +1,446 @@
+use crate::{
+    Options, Root, config_path,
+    error::{ErrorKind, Result},
+    git, manifest, write,
+};
+use schemars::JsonSchema;
+use semver::{BuildMetadata, Prerelease, Version};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    str::FromStr,
+};
+
+/// How to change a member's version.
+#[derive(Clone, Debug)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+    /// `1.2.3` -> `1.2.4-0`, `1.2.4-0` -> `1.2.4-1`, `1.2.4-rc.1` -> `1.2.4-rc.2`
+    Prerelease,
+    Exact(Version),
+}
+
+impl FromStr for Bump {
+    type Err = semver::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "major" => Bump::Major,
+            "minor" => Bump::Minor,
+            "patch" => Bump::Patch,
+            "prerelease" => Bump::Prerelease,
+            _ => Bump::Exact(s.parse()?),
+        })
+    }
+}
+
+impl Bump {
+    /// The version after applying this bump to `v`. Bumping a prerelease to the release it
+    /// precedes only drops the prerelease, so `2.0.0-1` bumped `major` is `2.0.0`.
+    pub fn apply(&self, v: &Version) -> Version {
+        let pre = !v.pre.is_empty();
+        let mut n = Version::new(v.major, v.minor, v.patch);
+        match self {
+            Bump::Major if pre && v.minor == 0 && v.patch == 0 => {}
+            Bump::Major => n = Version::new(v.major + 1, 0, 0),
+            Bump::Minor if pre && v.patch == 0 => {}
+            Bump::Minor => n = Version::new(v.major, v.minor + 1, 0),
+            Bump::Patch if pre => {}
+            Bump::Patch => n.patch += 1,
+            Bump::Prerelease if !pre => {
+                n.patch += 1;
+                n.pre = Prerelease::new("0").unwrap();
+            }
+            Bump::Prerelease => {
+                let mut ids = v.pre.split('.').map(str::to_owned).collect::<Vec<_>>();
+                match ids
+                    .iter_mut()
+                    .rev()
+                    .find_map(|i| i.parse::<u64>().ok().map(|n| (i, n)))
+                {
+                    Some((i, k)) => *i = (k + 1).to_string(),
+                    None => ids.push("0".to_owned()),
+                }
+                n.pre = Prerelease::new(&ids.join(".")).unwrap();
+            }
+            Bump::Exact(e) => n = e.clone(),
+        }
+        if !matches!(self, Bump::Exact(_)) {
+            n.build = BuildMetadata::EMPTY;
+        }
+        n
+    }
+}
+
+/// When bumping a member also bumps the patch version of the members that depend on it.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Cascade {
+    /// On every bump
+    #[default]
+    Patch,
+    /// Only when the dependency's new version is semver-incompatible with the old one: its
+    /// major version changed, or the first non-zero component for `0.x` versions
+    Major,
+    /// Never
+    None,
+}
+
+impl Cascade {
+    fn applies(self, old: &Version, new: &Version) -> bool {
+        let compat = |v: &Version| match (v.major, v.minor) {
+            (0, 0) => (0, 0, v.patch),
+            (0, m) => (0, m, 0),
+            (m, _) => (m, 0, 0),
+        };
+        match self {
+            Cascade::Patch => true,
+            Cascade::Major => compat(old) != compat(new),
+            Cascade::None => false,
+        }
+    }
+}
+
+fn parse(root_path: &str, root: &Root, xpath: &str) -> Result<Version> {
+    root.members[xpath]
+        .version
+        .parse()
+        .map_err(|e: semver::Error| {
+            ErrorKind::Config {
+                path: config_path(root_path, "pupi").unwrap_or_default(),
+                message: format!("[{xpath:?}].version: {e}"),
+            }
+            .into()
+        })
+}
+
+/// Apply `bumps` and, following `root.core.cascade`, bump everything that transitively depends
+/// on a bumped member. Returns the new version of every member that changed.
+pub fn plan(
+    root: &Root,
+    root_path: &str,
+    bumps: &BTreeMap<String, Bump>,
+) -> Result<BTreeMap<String, Version>> {
+    let cascade = root.core.as_ref().map(|c| c.cascade).unwrap_or_default();
+    let mut new = BTreeMap::new();
+    for (xpath, bump) in bumps {
+        if !root.members.contains_key(xpath) {
+            return Err(
+                ErrorKind::Selection(format!("`{xpath}` is not a member of {root_path}")).into(),
+            );
+        }
+        new.insert(xpath.clone(), bump.apply(&parse(root_path, root, xpath)?));
+    }
+    let mut queue = bumps.keys().cloned().collect::<VecDeque<_>>();
+    while let Some(x) = queue.pop_front() {
+        if !cascade.applies(&parse(root_path, root, &x)?, &new[&x]) {
+            continue;
+        }
+        for (d, m) in root.members.iter() {
+            if m.deps.contains_key(&x) && !new.contains_key(d) {
+                new.insert(d.clone(), Bump::Patch.apply(&parse(root_path, root, d)?));
+                queue.push_back(d.clone());
+            }
+        }
+    }
+    Ok(new)
+}
+
+/// What a conventional commit means for the next release, in increasing order of impact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Fix,
+    Feat,
+    Breaking,
+}
+
+impl Change {
+    /// Classify a commit by its conventional-commit header (`type(scope)!: ...`) and
+    /// `BREAKING CHANGE:` footer. Commits of other types, or not following the convention,
+    /// do not call for a release.
+    pub fn of(subject: &str, body: &str) -> Option<Change> {
+        let (head, _) = subject.split_once(':')?;
+        let bang = head.ends_with('!');
+        let head = head.trim_end_matches('!');
+        let ty = match head.split_once('(') {
+            Some((ty, scope)) if scope.ends_with(')') => ty,
+            Some(_) => return None,
+            None => head,
+        };
+        if ty.is_empty() || !ty.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let footer = body
+            .lines()
+            .any(|l| l.starts_with("BREAKING CHANGE:") || l.starts_with("BREAKING-CHANGE:"));
+        match ty {
+            _ if bang || footer => Some(Change::Breaking),
+            "feat" => Some(Change::Feat),
+            "fix" => Some(Change::Fix),
+            _ => None,
+        }
+    }
+
+    /// The bump this change calls for from `v`. Breaking changes to `0.x` versions bump the
+    /// minor version, as the major version stays 0 until the API is declared stable.
+    fn bump(self, v: &Version) -> Bump {
+        match self {
+            Change::Breaking if v.major == 0 => Bump::Minor,
+            Change::Breaking => Bump::Major,
+            Change::Feat => Bump::Minor,
+            Change::Fix => Bump::Patch,
+        }
+    }
+}
+
+/// The bump of every member with releasable commits under its `xpath` since its last release
+/// tag (or ever, if it has none). Members whose `version` is already past the computed next
+/// version are left alone.
+pub fn auto(root: &Root, root_path: &str, tags: &git::Tags) -> Result<BTreeMap<String, Bump>> {
+    let mut bumps = BTreeMap::new();
+    for xpath in root.members.keys() {
+        let current = parse(root_path, root, xpath)?;
+        let last = tags.last_release(xpath)?;
+        let commits = git::log(root_path, last.as_ref().map(|(_, t)| t.as_str()), xpath)?;
+        let Some(change) = commits.iter().filter_map(|(s, b)| Change::of(s, b)).max() else {
+            continue;
+        };
+        let base = last.map_or(current.clone(), |(v, _)| v);
+        let next = change.bump(&base).apply(&base);
+        if next > current {
+            bumps.insert(xpath.clone(), Bump::Exact(next));
+        }
+    }
+    Ok(bumps)
+}
+
+/// A YAML mapping key at the start of `line` (plain, single- or double-quoted), and the rest of
+/// the line after its `:`.
+fn yaml_key(line: &str) -> Option<(&str, &str)> {
+    let (key, rest) = match line.chars().next()? {
+        q @ ('"' | '\'') => {
+            let end = line[1..].find(q)? + 1;
+            (&line[1..end], &line[end + 1..])
+        }
+        _ => {
+            let end = line
+                .find(": ")
+                .or_else(|| line.strip_suffix(':').map(str::len))?;
+            (&line[..end], &line[end..])
+        }
+    };
+    Some((key, rest.strip_prefix(':')?))
+}
+
+/// Replace the scalar in `rest` (what follows `version:`), keeping its quotes, the space before
+/// it and any trailing comment.
+fn replace_scalar(rest: &str, version: &str) -> Option<String> {
+    let value = rest.trim_start();
+    let lead = &rest[..rest.len() - value.len()];
+    let (quote, end) = match value.chars().next()? {
+        q @ ('"' | '\'') => (Some(q), value[1..].find(q)? + 2),
+        _ => (None, value.find(" #").unwrap_or(value.len())),
+    };
+    let tail = &value[end..];
+    Some(match quote {
+        Some(q) => format!("{lead}{q}{version}{q}{tail}"),
+        None => format!("{lead}{version}{}", &value[value[..end].trim_end().len()..]),
+    })
+}
+
+/// Set the `version` of each member in the block-style YAML `content`, changing nothing else.
+/// `None` if a member's `version` is not a plain `version: ...` line under its key.
+fn set_yaml_versions(content: &str, versions: &BTreeMap<String, Version>) -> Option<String> {
+    let mut lines = content
+        .split_inclusive('\n')
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let indent = |l: &str| l.len() - l.trim_start().len();
+    let blank = |l: &str| matches!(l.trim_start().chars().next(), None | Some('#'));
+    for (xpath, version) in versions {
+        let start = lines.iter().position(|l| {
+            indent(l) == 0 && yaml_key(l.trim_end()).is_some_and(|(k, _)| k == xpath)
+        })?;
+        let block = lines[start + 1..]
+            .iter()
+            .position(|l| !blank(l) && indent(l) == 0)
+            .map_or(lines.len(), |n| start + 1 + n);
+        let child = lines[start + 1..block]
+            .iter()
+            .find(|l| !blank(l))
+            .map(|l| indent(l))?;
+        let line = lines[start + 1..block].iter_mut().find(|l| {
+            indent(l) == child && yaml_key(l.trim()).is_some_and(|(k, _)| k == "version")
+        })?;
+        let body = line.trim_end_matches(['\r', '\n']);
+        let newline = &line[body.len()..];
+        let (_, rest) = yaml_key(&body[child..])?;
+        let key = &body[..body.len() - rest.len()];
+        *line = format!(
+            "{key}{}{newline}",
+            replace_scalar(rest, &version.to_string())?
+        );
+    }
+    Some(lines.concat())
+}
+
+/// Write new member versions back to pupi.json/pupi.yaml, leaving everything else as it was.
+///
+/// pupi.yaml is edited line by line. Only a layout that cannot be edited that way, such as a
+/// flow-style mapping, is re-serialized, which drops its comments and formatting.
+pub fn save(opts: &Options, root_path: &str, versions: &BTreeMap<String, Version>) -> Result<()> {
+    let Some(path) = config_path(root_path, "pupi") else {
+        return Ok(());
+    };
+    let invalid = |e: &dyn std::fmt::Display| ErrorKind::Config {
+        path: path.clone(),
+        message: e.to_string(),
+    };
+    let content = std::fs::read_to_string(&path)?;
+    let contents = if path.ends_with(".json") {
+        let mut val: serde_json::Value = serde_json::from_str(&content).map_err(|e| invalid(&e))?;
+        for (xpath, v) in versions {
+            if let Some(m) = val.get_mut(xpath).and_then(|m| m.as_object_mut()) {
+                m.insert("version".to_owned(), v.to_string().into());
+            }
+        }
+        manifest::json(&val, &path)?
+    } else if let Some(edited) = set_yaml_versions(&content, versions) {
+        edited.into_bytes()
+    } else {
+        let mut val: serde_yml::Value = serde_yml::from_str(&content).map_err(|e| invalid(&e))?;
+        for (xpath, v) in versions {
+            if let Some(m) = val.get_mut(xpath.as_str()).and_then(|m| m.as_mapping_mut()) {
+                m.insert("version".into(), v.to_string().into());
+            }
+        }
+        serde_yml::to_string(&val)
+            .map_err(|e| invalid(&e))?
+            .into_bytes()
+    };
+    write(opts, &path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn bump_apply() {
+        for (from, bump, to) in [
+            ("1.2.3", "major", "2.0.0"),
+            ("1.2.3", "minor", "1.3.0"),
+            ("1.2.3", "patch", "1.2.4"),
+            ("1.2.3+build.1", "patch", "1.2.4"),
+            ("0.1.9", "minor", "0.2.0"),
+            ("1.2.3", "prerelease", "1.2.4-0"),
+            ("1.2.4-0", "prerelease", "1.2.4-1"),
+            ("1.2.4-rc.1", "prerelease", "1.2.4-rc.2"),
+            ("1.2.4-rc", "prerelease", "1.2.4-rc.0"),
+            ("1.2.4-rc.1.beta", "prerelease", "1.2.4-rc.2.beta"),
+            ("2.0.0-1", "major", "2.0.0"),
+            ("2.1.0-1", "major", "3.0.0"),
+            ("1.3.0-1", "minor", "1.3.0"),
+            ("1.3.1-1", "minor", "1.4.0"),
+            ("1.2.4-0", "patch", "1.2.4"),
+            ("1.2.3", "5.0.0-beta+x", "5.0.0-beta+x"),
+        ] {
+            let bump: Bump = bump.parse().unwrap();
+            assert_eq!(bump.apply(&v(from)), v(to), "{from} {bump:?}");
+        }
+        assert!("next".parse::<Bump>().is_err());
+    }
+
+    #[test]
+    fn cascade_applies() {
+        for (old, new, major) in [
+            ("1.2.3", "1.2.4", false),
+            ("1.2.3", "1.3.0", false),
+            ("1.2.3", "2.0.0", true),
+            ("0.1.2", "0.1.3", false),
+            ("0.1.2", "0.2.0", true),
+            ("0.0.1", "0.0.2", true),
+            ("0.9.0", "1.0.0", true),
+        ] {
+            assert!(Cascade::Patch.applies(&v(old), &v(new)));
+            assert_eq!(
+                Cascade::Major.applies(&v(old), &v(new)),
+                major,
+                "{old} -> {new}"
+            );
+            assert!(!Cascade::None.applies(&v(old), &v(new)));
+        }
+    }
+
+    #[test]
+    fn change_of() {
+        use Change::*;
+        for (subject, body, change) in [
+            ("fix: crash", "", Some(Fix)),
+            ("feat: add flag", "", Some(Feat)),
+            ("feat(cli): add flag", "", Some(Feat)),
+            ("fix(core)!: drop API", "", Some(Breaking)),
+            ("chore!: drop node 16", "", Some(Breaking)),
+            ("refactor: x", "BREAKING CHANGE: removed y", Some(Breaking)),
+            ("fix: x", "details\n\nBREAKING-CHANGE: y", Some(Breaking)),
+            ("fix: x", "mentions BREAKING CHANGE: inline", Some(Fix)),
+            ("chore: bump deps", "", None),
+            ("docs(readme): typo", "", None),
+            ("Merge branch 'main'", "", None),
+            ("feat(cli: missing paren", "", None),
+            ("feat (cli): space", "", None),
+            (": no type", "", None),
+            ("fix-up: x", "", None),
+        ] {
+            assert_eq!(Change::of(subject, body), change, "{subject:?} {body:?}");
+        }
+    }
+
+    #[test]
+    fn change_bump() {
+        for (change, from, to) in [
+            (Change::Breaking, "1.2.3", "2.0.0"),
+            (Change::Breaking, "0.2.3", "0.3.0"),
+            (Change::Breaking, "0.0.3", "0.1.0"),
+            (Change::Feat, "1.2.3", "1.3.0"),
+            (Change::Feat, "0.2.3", "0.3.0"),
+            (Change::Fix, "1.2.3", "1.2.4"),
+            (Change::Fix, "0.0.3", "0.0.4"),
+        ] {
+            assert_eq!(change.bump(&v(from)).apply(&v(from)), v(to), "{from}");
+        }
+    }
+
+    #[test]
+    fn yaml_versions_in_place() {
+        let versions = BTreeMap::from([
+            ("pkg/core".to_owned(), v("1.3.0")),
+            ("web".to_owned(), v("0.2.0")),
+        ]);
+        let yaml = "# workspace\n\
+                    \"pkg/core\":\n\
+                    \x20 # the core\n\
+                    \x20 deps: {}\n\
+                    \x20 version: 1.2.3 # bumped by pupi\n\
+                    \x20 description: Core\n\
+                    \n\
+                    web:\n\
+                    \x20   version: '0.1.0'\n\
+                    \x20   npm:\n\
+                    \x20     version: keep\n";
+        let expected = yaml
+            .replace("1.2.3 #", "1.3.0 #")
+            .replace("'0.1.0'", "'0.2.0'");
+        assert_eq!(set_yaml_versions(yaml, &versions).unwrap(), expected);
+        assert_eq!(
+            set_yaml_versions("web: {version: 0.1.0}\n", &versions),
+            None
+        );
+    }
+}